@@ -0,0 +1,201 @@
+use ethers::prelude::*;
+
+const COMPACT_SUFFIXES: &[&str] = &["", "K", "M", "B", "T"];
+
+/// How token amounts are rendered in the feed
+#[derive(Clone, Debug)]
+pub struct AmountFormat {
+    /// round to this many significant digits, `None` prints every digit
+    pub significant_digits: Option<usize>,
+    /// group the integer part as `25,000`
+    pub thousands_separator: bool,
+    /// shorten large amounts as `1.25M`
+    pub compact: bool,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            significant_digits: Some(6),
+            thousands_separator: true,
+            compact: false,
+        }
+    }
+}
+
+/// Renders `amount` as a decimal with `decimals` places. The conversion works
+/// on the base 10 digits of the `U256` so nothing is lost to f64 or u128.
+pub fn format_amount(amount: &U256, decimals: usize, format: &AmountFormat) -> String {
+    format_digits(&amount.to_string(), decimals, format)
+}
+
+fn format_digits(digits: &str, decimals: usize, format: &AmountFormat) -> String {
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (int_digits, frac_digits) = padded.split_at(padded.len() - decimals);
+
+    let mut int_part = int_digits.trim_start_matches('0').to_string();
+    let mut frac_part = frac_digits.to_string();
+    let mut suffix = 0;
+
+    if format.compact {
+        while int_part.len() > 3 && suffix < COMPACT_SUFFIXES.len() - 1 {
+            shift_thousands(&mut int_part, &mut frac_part);
+            suffix += 1;
+        }
+    }
+
+    if let Some(significant_digits) = format.significant_digits {
+        let significant_digits = significant_digits.max(1);
+        // integer digits are never rounded away, only the fraction is
+        let keep = if int_part.is_empty() {
+            let leading_zeros = frac_part.len() - frac_part.trim_start_matches('0').len();
+            leading_zeros + significant_digits
+        } else {
+            significant_digits.saturating_sub(int_part.len())
+        };
+
+        if keep < frac_part.len() {
+            let round_up = frac_part.as_bytes()[keep] >= b'5';
+            frac_part.truncate(keep);
+            if round_up {
+                round_up_last_digit(&mut int_part, &mut frac_part);
+            }
+        }
+    }
+
+    // rounding 999.9996K up lands on 1000K, move that to 1M
+    if format.compact && int_part.len() > 3 && suffix < COMPACT_SUFFIXES.len() - 1 {
+        shift_thousands(&mut int_part, &mut frac_part);
+        suffix += 1;
+    }
+
+    let frac_part = frac_part.trim_end_matches('0');
+    let int_part = if int_part.is_empty() {
+        "0".to_string()
+    } else if format.thousands_separator {
+        group_thousands(&int_part)
+    } else {
+        int_part
+    };
+
+    if frac_part.is_empty() {
+        format!("{}{}", int_part, COMPACT_SUFFIXES[suffix])
+    } else {
+        format!("{}.{}{}", int_part, frac_part, COMPACT_SUFFIXES[suffix])
+    }
+}
+
+fn shift_thousands(int_part: &mut String, frac_part: &mut String) {
+    let split = int_part.len() - 3;
+    frac_part.insert_str(0, &int_part[split..]);
+    int_part.truncate(split);
+}
+
+fn round_up_last_digit(int_part: &mut String, frac_part: &mut String) {
+    let frac_len = frac_part.len();
+    let mut digits: Vec<u8> = format!("{}{}", int_part, frac_part).into_bytes();
+
+    let mut carry = true;
+    for digit in digits.iter_mut().rev() {
+        if *digit == b'9' {
+            *digit = b'0';
+        } else {
+            *digit += 1;
+            carry = false;
+            break;
+        }
+    }
+    if carry {
+        digits.insert(0, b'1');
+    }
+
+    let digits = String::from_utf8(digits).expect("digits are ascii");
+    let (new_int, new_frac) = digits.split_at(digits.len() - frac_len);
+    *int_part = new_int.to_string();
+    *frac_part = new_frac.to_string();
+}
+
+fn group_thousands(int_part: &str) -> String {
+    let mut grouped = String::with_capacity(int_part.len() + int_part.len() / 3);
+    for (i, digit) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exact() -> AmountFormat {
+        AmountFormat {
+            significant_digits: None,
+            thousands_separator: false,
+            compact: false,
+        }
+    }
+
+    #[test]
+    fn rounding_carries_into_the_integer_part() {
+        let format = AmountFormat {
+            significant_digits: Some(6),
+            ..exact()
+        };
+        assert_eq!(format_amount(&U256::from(9_999_996), 7, &format), "1");
+    }
+
+    #[test]
+    fn compact_suffix_rolls_over_after_rounding() {
+        let format = AmountFormat {
+            significant_digits: Some(6),
+            compact: true,
+            ..exact()
+        };
+        // 999.9996K
+        assert_eq!(format_amount(&U256::from(9_999_996), 1, &format), "1M");
+    }
+
+    #[test]
+    fn zero_decimals() {
+        assert_eq!(format_amount(&U256::from(12_345), 0, &exact()), "12345");
+        assert_eq!(format_amount(&U256::zero(), 0, &exact()), "0");
+        assert_eq!(
+            format_amount(&U256::from(12_345), 0, &AmountFormat::default()),
+            "12,345"
+        );
+    }
+
+    #[test]
+    fn values_above_u128_max() {
+        let above_u128 = U256::from(u128::MAX) + 1;
+        assert_eq!(
+            format_amount(&above_u128, 0, &exact()),
+            "340282366920938463463374607431768211456"
+        );
+        assert_eq!(
+            format_amount(&U256::MAX, 18, &exact()),
+            "115792089237316195423570985008687907853269984665640564039457.584007913129639935"
+        );
+    }
+
+    #[test]
+    fn thousands_grouping() {
+        let format = AmountFormat {
+            thousands_separator: true,
+            ..exact()
+        };
+        assert_eq!(
+            format_amount(&U256::from(1_234_567), 0, &format),
+            "1,234,567"
+        );
+        assert_eq!(format_amount(&U256::from(100_000), 0, &format), "100,000");
+        assert_eq!(format_amount(&U256::from(123), 0, &format), "123");
+        assert_eq!(
+            format_amount(&U256::from(123_456_789), 2, &format),
+            "1,234,567.89"
+        );
+    }
+}
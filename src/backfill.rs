@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use ethers::prelude::*;
+use futures::stream::{self, StreamExt};
+use paris::Logger;
+
+use crate::{
+    pipeline::{fetch_block_data, log_block_data, FeedContext},
+    reorg::{block_number, RecentBlocks},
+    sinks::Sinks,
+};
+
+/// Blocks processed so far out of a backfill, for the rate and ETA
+pub struct BackfillProgress {
+    started: Instant,
+    total: u64,
+    processed: u64,
+}
+
+impl BackfillProgress {
+    pub fn new(total: u64) -> BackfillProgress {
+        BackfillProgress {
+            started: Instant::now(),
+            total,
+            processed: 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.processed += 1;
+    }
+
+    pub fn log_str(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.processed as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if rate > 0.0 {
+            format_duration(Duration::from_secs_f64(
+                (self.total - self.processed) as f64 / rate,
+            ))
+        } else {
+            "unknown".to_string()
+        };
+        format!(
+            "Backfilled {}/{} blocks, {:.1} blocks/s, ETA {}",
+            self.processed, self.total, rate, eta
+        )
+    }
+}
+
+/// Replays blocks `from..to`, fetching up to `concurrency` of them at once.
+/// `buffered` hands results back in block order, so the feed reads the same as
+/// a sequential replay. The latest replayed blocks are kept in `recent`, so a
+/// reorg right after the replay can still take them back.
+pub async fn backfill(
+    ctx: &FeedContext<'_>,
+    from: U64,
+    to: U64,
+    concurrency: usize,
+    sinks: &mut Sinks,
+    recent: &mut RecentBlocks,
+    logger: &mut Logger<'_>,
+) -> anyhow::Result<()> {
+    if from >= to {
+        return Ok(());
+    }
+
+    let mut progress = BackfillProgress::new((to - from).as_u64());
+    let mut blocks = stream::iter(from.as_u64()..to.as_u64())
+        .map(|number| {
+            fetch_block_data(
+                ctx.client,
+                ctx.router,
+                U64::from(number),
+                ctx.filters,
+                ctx.feed_config,
+            )
+        })
+        .buffered(concurrency.max(1));
+
+    let text = ctx.feed_config.output.logs_progress();
+    while let Some(block_data) = blocks.next().await {
+        let block_data = block_data?;
+        if text {
+            logger
+                .done()
+                .info(format!("Block {}", &block_data.block.hash.unwrap()));
+        }
+        log_block_data(&block_data, ctx.token_map, ctx.feed_config, sinks).await?;
+
+        let number = block_number(&block_data);
+        recent.push(block_data);
+        recent.prune(number);
+
+        progress.tick();
+        if text {
+            logger.loading(progress.log_str());
+        }
+    }
+    if text {
+        logger.done();
+    }
+
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, (seconds % 3600) / 60),
+    }
+}
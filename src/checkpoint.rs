@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The last block every sink has fully written
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Checkpoint {
+    pub number: u64,
+    pub hash: H256,
+}
+
+impl Checkpoint {
+    pub fn from_block(block: &Block<Transaction>) -> Checkpoint {
+        Checkpoint {
+            number: block.number.expect("mined blocks have a number").as_u64(),
+            hash: block.hash.expect("mined blocks have a hash"),
+        }
+    }
+
+    /// Where the chain stands once `block` is reorganized out
+    pub fn parent_of(block: &Block<Transaction>) -> Checkpoint {
+        Checkpoint {
+            number: block
+                .number
+                .expect("mined blocks have a number")
+                .as_u64()
+                .saturating_sub(1),
+            hash: block.parent_hash,
+        }
+    }
+}
+
+/// A `--checkpoint-file`, a small JSON object rewritten after every block
+pub struct CheckpointFile {
+    path: PathBuf,
+}
+
+impl CheckpointFile {
+    pub fn new(path: PathBuf) -> CheckpointFile {
+        CheckpointFile { path }
+    }
+
+    /// `None` until the first block is saved
+    pub fn load(&self) -> anyhow::Result<Option<Checkpoint>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Can't read {}", self.path.display()))
+            }
+        };
+        let checkpoint = serde_json::from_str(&contents)
+            .with_context(|| format!("{} isn't a checkpoint", self.path.display()))?;
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Written next to the file then renamed over it, so a crash never leaves
+    /// half a checkpoint behind
+    pub fn save(&self, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        fs::write(
+            &tmp_path,
+            serde_json::to_string(checkpoint).expect("checkpoints always serialize"),
+        )
+        .with_context(|| format!("Can't write {}", self.path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Can't write {}", self.path.display()))?;
+
+        Ok(())
+    }
+}
@@ -1,7 +1,16 @@
 use std::env;
+use std::path::PathBuf;
 
-use clap::{App, Arg};
+use anyhow::bail;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use clap::{ArgEnum, Args, Parser, Subcommand};
 use dotenv::dotenv;
+use ethers::prelude::*;
+
+use crate::{
+    amount_format::AmountFormat,
+    filters::{MinValue, TokenMatch},
+};
 
 //Infura Constants
 const INFURA_WS_ENDPOINT: &str = "wss://mainnet.infura.io/ws/v3";
@@ -14,51 +23,331 @@ const INFURA_HTTP_ENDPOINT: &str = "https://mainnet.infura.io/v3";
 // Geth Constants
 // pub const GETH_HTTP_ENDPOINT: &str = "http:H//localhost:8545";
 
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum RevertedMode {
+    /// print reverted transactions with a REVERTED marker
+    Mark,
+    /// drop reverted transactions from the feed
+    Hide,
+}
+
+/// How the feed is written to stdout
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// one human readable line per transaction
+    Text,
+    /// one JSON object per transaction, see the README for the schema
+    Jsonl,
+    /// one row per swap written to --out-file
+    Csv,
+}
+
+impl OutputFormat {
+    /// block headers and progress stay off stdout when it carries records
+    pub fn logs_progress(&self) -> bool {
+        *self != OutputFormat::Jsonl
+    }
+}
+
+#[derive(Parser)]
+#[clap(
+    name = "uni-listen",
+    version = "0.1",
+    about = "A simple cli app used to watch Uniswap V2 Router",
+    author = "Devin Riley"
+)]
+struct Cli {
+    #[clap(long, default_value = INFURA_WS_ENDPOINT, global = true)]
+    ws_provider_url: String,
+    #[clap(long, default_value = INFURA_HTTP_ENDPOINT, global = true)]
+    http_provider_url: String,
+    #[clap(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Follow new blocks, optionally catching up on older ones first
+    Watch {
+        #[clap(flatten)]
+        start: StartArgs,
+        #[clap(flatten)]
+        confirmations: ConfirmationArgs,
+        #[clap(flatten)]
+        feed: FeedArgs,
+        #[clap(flatten)]
+        filters: FilterArgs,
+        #[clap(flatten)]
+        sinks: SinkArgs,
+    },
+    /// Replay a window of past blocks and exit
+    Backfill {
+        #[clap(flatten)]
+        start: StartArgs,
+        /// Replay blocks A through B inclusive, given as A..B
+        #[clap(long, group = "start", conflicts_with = "to-block")]
+        range: Option<BlockRange>,
+        /// Last block to replay, the chain head when unset
+        #[clap(long)]
+        to_block: Option<u64>,
+        #[clap(flatten)]
+        feed: FeedArgs,
+        #[clap(flatten)]
+        filters: FilterArgs,
+        #[clap(flatten)]
+        sinks: SinkArgs,
+    },
+    /// Print every detail of a single router transaction, failing if it doesn't decode
+    Decode {
+        /// Hash of the transaction to decode
+        tx_hash: TxHash,
+        #[clap(flatten)]
+        feed: FeedArgs,
+    },
+    /// Decode raw router calldata offline, read from stdin when not given
+    DecodeCalldata {
+        /// Hex encoded calldata, with or without the 0x prefix
+        calldata: Option<String>,
+        /// ETH sent along with the call, in wei
+        #[clap(long, default_value = "0", parse(try_from_str = U256::from_dec_str))]
+        value: U256,
+        #[clap(flatten)]
+        amounts: AmountArgs,
+    },
+    /// Search the token list by symbol or address
+    Tokens {
+        /// Symbol or address to look for, every token when unset
+        query: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct StartArgs {
+    /// Start at this block
+    #[clap(long, group = "start")]
+    since_block: Option<u64>,
+    /// Start this many blocks behind the head
+    #[clap(long = "include-prev-n-blocks", group = "start")]
+    prev_blocks: Option<u32>,
+    /// Start at the first block mined at or after this UTC time, e.g. "2026-10-01T00:00Z"
+    #[clap(long, group = "start", parse(try_from_str = parse_timestamp))]
+    since: Option<u64>,
+    /// Continue after the last block in --checkpoint-file or the --sqlite database
+    #[clap(long, group = "start")]
+    resume: bool,
+    /// How many historical blocks to fetch at once
+    #[clap(long, default_value = "8")]
+    concurrency: usize,
+}
+
+#[derive(Args)]
+struct ConfirmationArgs {
+    /// Only log a block once this many blocks are built on top of it
+    #[clap(long, default_value = "0", conflicts_with = "finalized")]
+    confirmations: u64,
+    /// Only log blocks the beacon chain has finalized
+    #[clap(long)]
+    finalized: bool,
+    /// Also print blocks still waiting to be confirmed, marked provisional. Text output only
+    #[clap(long)]
+    provisional: bool,
+}
+
+#[derive(Args)]
+struct AmountArgs {
+    /// Round amounts to this many significant digits, 6 by default
+    #[clap(long, conflicts_with = "exact-amounts")]
+    significant_digits: Option<usize>,
+    /// Print amounts with every decimal, unrounded
+    #[clap(long)]
+    exact_amounts: bool,
+    /// Shorten large amounts with K, M, B and T suffixes
+    #[clap(long)]
+    compact_amounts: bool,
+    /// Print amounts without thousands separators
+    #[clap(long)]
+    no_thousands_separator: bool,
+}
+
+#[derive(Args)]
+struct FeedArgs {
+    #[clap(flatten)]
+    amounts: AmountArgs,
+    #[clap(long, arg_enum, default_value = "text")]
+    output: OutputFormat,
+    /// Fetch receipts to report the amounts each swap actually executed
+    #[clap(long)]
+    with_receipts: bool,
+    /// Fetch receipts to mark or hide reverted transactions
+    #[clap(long, arg_enum)]
+    reverted: Option<RevertedMode>,
+    /// Quote swaps at the parent block to report the slippage tolerance used
+    #[clap(long)]
+    slippage: bool,
+    /// Fetch receipts to report gas cost, priority tip and transaction type
+    #[clap(long)]
+    gas: bool,
+    /// ETH price for --min-value, quoted from WETH/USDC when unset. USD gas costs
+    /// are only shown when it is given
+    #[clap(long)]
+    eth_usd_price: Option<f64>,
+}
+
+#[derive(Args)]
+struct SinkArgs {
+    /// Where --output csv writes its rows, truncated on start
+    #[clap(long, required_if_eq("output", "csv"))]
+    out_file: Option<PathBuf>,
+    /// Also store blocks, transactions and swaps in this SQLite database
+    #[clap(long)]
+    sqlite: Option<PathBuf>,
+    /// Record the last fully processed block in this file, for --resume
+    #[clap(long)]
+    checkpoint_file: Option<PathBuf>,
+    /// Also upsert executed swaps into PostgreSQL, at DATABASE_URL when no url is given
+    #[clap(long, value_name = "URL")]
+    postgres: Option<Option<String>>,
+}
+
+#[derive(Args)]
+struct FilterArgs {
+    /// Only print swaps routed through this token, a symbol or an address. Repeatable
+    #[clap(long = "token", multiple_occurrences = true)]
+    tokens: Vec<String>,
+    /// Match --token anywhere in the swap path (default)
+    #[clap(long, requires = "tokens", conflicts_with = "endpoints-only")]
+    any_hop: bool,
+    /// Match --token only as the token sold or bought
+    #[clap(long, requires = "tokens")]
+    endpoints_only: bool,
+    /// Only print swaps worth at least this much, e.g. "5 ETH" or "10k USD"
+    #[clap(long)]
+    min_value: Option<MinValue>,
+}
+
+/// `A..B`, both ends included
+#[derive(Clone, Copy)]
+struct BlockRange(u64, u64);
+
+impl std::str::FromStr for BlockRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || -> Option<BlockRange> {
+            let (from, to) = s.split_once("..")?;
+            Some(BlockRange(
+                from.trim().parse().ok()?,
+                to.trim().parse().ok()?,
+            ))
+        };
+        parse().ok_or_else(|| format!("expected A..B, got \"{}\"", s))
+    }
+}
+
+/// Where a replay of past blocks starts
+#[derive(Clone, Copy, PartialEq)]
+pub enum BlockStart {
+    Block(u64),
+    /// this many blocks behind the head
+    PrevBlocks(u32),
+    /// first block mined at or after this unix time in seconds
+    Timestamp(u64),
+    /// the block after the sinks' checkpoint
+    Resume,
+}
+
+/// When a live block is deep enough to be logged and written to the sinks
+#[derive(Clone, Copy, PartialEq)]
+pub enum Confirmation {
+    /// this many blocks on top of it, 0 logs every head straight away
+    Depth(u64),
+    /// once the `finalized` block reaches it
+    Finalized,
+}
+
+pub struct ConfirmationConfig {
+    pub confirmation: Confirmation,
+    /// print blocks that aren't confirmed yet, they never reach the sinks
+    pub provisional: bool,
+}
+
+/// A replay of past blocks, `start..=to_block`
+pub struct BackfillConfig {
+    /// `None` skips the replay
+    pub start: Option<BlockStart>,
+    /// the chain head when `None`
+    pub to_block: Option<u64>,
+    /// blocks fetched at once
+    pub concurrency: usize,
+}
+
+/// How decoded transactions are rendered, and what is fetched to render them
+pub struct FeedConfig {
+    pub output: OutputFormat,
+    pub amount_format: AmountFormat,
+    pub with_receipts: bool,
+    pub reverted: RevertedMode,
+    pub slippage: bool,
+    pub gas: bool,
+    pub eth_usd_price: Option<f64>,
+}
+
+/// Files and databases decoded blocks are written to
+pub struct SinkConfig {
+    /// `--output csv` rows
+    pub out_file: Option<PathBuf>,
+    pub sqlite: Option<PathBuf>,
+    /// connection string, rows need receipts so setting it fetches them
+    pub postgres: Option<String>,
+    pub checkpoint_file: Option<PathBuf>,
+}
+
+/// Which swaps make it into the feed
+pub struct FilterConfig {
+    pub tokens: Vec<String>,
+    pub token_match: TokenMatch,
+    pub min_value: Option<MinValue>,
+}
+
+pub enum Command {
+    Watch {
+        catch_up: BackfillConfig,
+        confirmations: ConfirmationConfig,
+        feed: FeedConfig,
+        filters: FilterConfig,
+        sinks: SinkConfig,
+    },
+    Backfill {
+        window: BackfillConfig,
+        feed: FeedConfig,
+        filters: FilterConfig,
+        sinks: SinkConfig,
+    },
+    Decode {
+        tx_hash: TxHash,
+        feed: FeedConfig,
+    },
+    DecodeCalldata {
+        /// `None` reads it from stdin
+        calldata: Option<String>,
+        value: U256,
+        amount_format: AmountFormat,
+    },
+    Tokens {
+        query: Option<String>,
+    },
+}
+
 pub struct UniListenConfig {
     pub ws_url: String,
     pub http_url: String,
-    pub since_block: Option<u64>,
-    pub prev_blocks: Option<u32>,
-    pub watch_blocks: bool,
+    pub command: Command,
 }
 
-pub fn get_config() -> UniListenConfig {
+pub fn get_config() -> anyhow::Result<UniListenConfig> {
     dotenv().ok();
 
-    let ws_provider_opt = Arg::new("ws-provider-url")
-        .long("ws-provider-url")
-        .default_value(INFURA_WS_ENDPOINT)
-        .group("provider");
-    let http_provider_opt = Arg::new("http-provider-url")
-        .default_value(INFURA_HTTP_ENDPOINT)
-        .long("http-provider-url")
-        .group("provider");
-
-    let since_block_opt = Arg::new("since-block")
-        .long("since-block")
-        .takes_value(true)
-        .group("time-machine");
-    let include_prev_blocks = Arg::new("prev-blocks")
-        .long("include-prev-n-blocks")
-        .takes_value(true)
-        .group("time-machine");
-    let watch_new_blocks = Arg::new("watch-blocks")
-        .long("watch-new-blocks")
-        .default_value("true")
-        .group("time-machine");
-
-    let matches = App::new("uni-listen")
-        .version("0.1")
-        .about("A simple cli app used to watch Uniswap V2 Router")
-        .author("Devin Riley")
-        .args([
-            ws_provider_opt,
-            http_provider_opt,
-            since_block_opt,
-            include_prev_blocks,
-            watch_new_blocks,
-        ])
-        .get_matches();
+    let cli = Cli::parse();
 
     let http_id: Option<String> = match env::var("HTTP_PROJECT_ID") {
         Ok(s) => Some(s),
@@ -75,47 +364,299 @@ pub fn get_config() -> UniListenConfig {
         None => url,
     };
 
-    let http_url = build_url(
-        matches
-            .value_of("http-provider-url")
-            .unwrap()
-            .to_lowercase(),
-        http_id,
-    );
-
-    let ws_url = build_url(
-        matches.value_of("ws-provider-url").unwrap().to_lowercase(),
-        ws_id,
-    );
-
-    let since_block = matches.value_of("since-block").map(|s| {
-        s.parse::<u64>()
-            .expect("--since-block format must be a u64")
-    });
-
-    let prev_blocks = matches.value_of("prev-blocks").map(|s| {
-        s.parse::<u32>()
-            .expect("--include-prev-n-blocks format must be a u32")
-    });
-
-    if since_block.is_some() && prev_blocks.is_some() {
-        panic!("Can't set --since-block and --include-prev-n-blocks");
-    }
-
-    let watch_blocks = match matches.value_of("watch-blocks") {
-        Some(s) => match s.to_lowercase().as_str() {
-            "true" => true,
-            "false" => false,
-            _ => panic!("--watch-blocks must be \"true\" or \"false\""),
+    let http_url = build_url(cli.http_provider_url.to_lowercase(), http_id);
+    let ws_url = build_url(cli.ws_provider_url.to_lowercase(), ws_id);
+
+    let command = match cli.command {
+        CliCommand::Watch {
+            start,
+            confirmations,
+            feed,
+            filters,
+            sinks,
+        } => {
+            let catch_up = start.into_backfill_config(None);
+            let (feed, sinks) = feed_and_sinks(feed, sinks)?;
+            check_resume(&catch_up, &sinks)?;
+            let confirmations = ConfirmationConfig::from(confirmations);
+            if confirmations.provisional {
+                if confirmations.confirmation == Confirmation::Depth(0) {
+                    bail!("--provisional needs --confirmations or --finalized");
+                }
+                if feed.output != OutputFormat::Text {
+                    bail!("--provisional only works with --output text");
+                }
+            }
+            Command::Watch {
+                catch_up,
+                confirmations,
+                feed,
+                filters: filters.into(),
+                sinks,
+            }
+        }
+        CliCommand::Backfill {
+            start,
+            range,
+            to_block,
+            feed,
+            filters,
+            sinks,
+        } => {
+            let window = match range {
+                Some(BlockRange(from, to)) => BackfillConfig {
+                    start: Some(BlockStart::Block(from)),
+                    to_block: Some(to),
+                    concurrency: start.concurrency,
+                },
+                None => start.into_backfill_config(to_block),
+            };
+            match (window.start, window.to_block) {
+                (None, _) => bail!(
+                    "backfill needs a start, one of --since-block, --include-prev-n-blocks, --since, --resume or --range"
+                ),
+                (Some(BlockStart::Block(from)), Some(to)) if from > to => {
+                    bail!("Block range ends at {} before it starts at {}", to, from)
+                }
+                _ => {}
+            }
+            let (feed, sinks) = feed_and_sinks(feed, sinks)?;
+            check_resume(&window, &sinks)?;
+            Command::Backfill {
+                window,
+                feed,
+                filters: filters.into(),
+                sinks,
+            }
+        }
+        CliCommand::Decode { tx_hash, feed } => Command::Decode {
+            tx_hash,
+            feed: feed.into(),
+        },
+        CliCommand::DecodeCalldata {
+            calldata,
+            value,
+            amounts,
+        } => Command::DecodeCalldata {
+            calldata,
+            value,
+            amount_format: amounts.into(),
         },
-        None => true,
+        CliCommand::Tokens { query } => Command::Tokens { query },
     };
 
-    UniListenConfig {
-        watch_blocks,
-        http_url,
+    Ok(UniListenConfig {
         ws_url,
-        prev_blocks,
-        since_block,
+        http_url,
+        command,
+    })
+}
+
+impl StartArgs {
+    fn into_backfill_config(self, to_block: Option<u64>) -> BackfillConfig {
+        let start = if self.resume {
+            Some(BlockStart::Resume)
+        } else if let Some(block) = self.since_block {
+            Some(BlockStart::Block(block))
+        } else if let Some(prev_blocks) = self.prev_blocks {
+            Some(BlockStart::PrevBlocks(prev_blocks))
+        } else {
+            self.since.map(BlockStart::Timestamp)
+        };
+
+        BackfillConfig {
+            start,
+            to_block,
+            concurrency: self.concurrency.max(1),
+        }
+    }
+}
+
+impl From<ConfirmationArgs> for ConfirmationConfig {
+    fn from(args: ConfirmationArgs) -> Self {
+        let confirmation = if args.finalized {
+            Confirmation::Finalized
+        } else {
+            Confirmation::Depth(args.confirmations)
+        };
+
+        ConfirmationConfig {
+            confirmation,
+            provisional: args.provisional,
+        }
+    }
+}
+
+impl From<AmountArgs> for AmountFormat {
+    fn from(args: AmountArgs) -> Self {
+        let default_format = AmountFormat::default();
+        let significant_digits = if args.exact_amounts {
+            None
+        } else {
+            args.significant_digits
+                .or(default_format.significant_digits)
+        };
+
+        AmountFormat {
+            significant_digits,
+            thousands_separator: !args.no_thousands_separator,
+            compact: args.compact_amounts,
+        }
+    }
+}
+
+impl From<FeedArgs> for FeedConfig {
+    fn from(args: FeedArgs) -> Self {
+        let amount_format = args.amounts.into();
+
+        // reverts and executed amounts are only visible once receipts are fetched
+        let with_receipts =
+            args.with_receipts || args.reverted.is_some() || args.slippage || args.gas;
+
+        FeedConfig {
+            output: args.output,
+            amount_format,
+            with_receipts,
+            reverted: args.reverted.unwrap_or(RevertedMode::Mark),
+            slippage: args.slippage,
+            gas: args.gas,
+            eth_usd_price: args.eth_usd_price,
+        }
+    }
+}
+
+impl TryFrom<SinkArgs> for SinkConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(args: SinkArgs) -> anyhow::Result<Self> {
+        let postgres = match args.postgres {
+            Some(Some(url)) => Some(url),
+            Some(None) => match env::var("DATABASE_URL") {
+                Ok(url) => Some(url),
+                Err(_) => bail!("--postgres needs a url or DATABASE_URL in the env"),
+            },
+            None => None,
+        };
+
+        Ok(SinkConfig {
+            out_file: args.out_file,
+            sqlite: args.sqlite,
+            postgres,
+            checkpoint_file: args.checkpoint_file,
+        })
+    }
+}
+
+// postgres rows are keyed on the pair events in the receipts
+fn feed_and_sinks(feed: FeedArgs, sinks: SinkArgs) -> anyhow::Result<(FeedConfig, SinkConfig)> {
+    let sinks: SinkConfig = sinks.try_into()?;
+    let mut feed: FeedConfig = feed.into();
+    feed.with_receipts |= sinks.postgres.is_some();
+
+    Ok((feed, sinks))
+}
+
+fn check_resume(backfill_config: &BackfillConfig, sinks: &SinkConfig) -> anyhow::Result<()> {
+    if backfill_config.start == Some(BlockStart::Resume)
+        && sinks.checkpoint_file.is_none()
+        && sinks.sqlite.is_none()
+    {
+        bail!("--resume reads the checkpoint from --checkpoint-file or --sqlite, set one of them");
+    }
+
+    Ok(())
+}
+
+impl From<FilterArgs> for FilterConfig {
+    fn from(args: FilterArgs) -> Self {
+        let token_match = if args.endpoints_only {
+            TokenMatch::EndpointsOnly
+        } else {
+            TokenMatch::AnyHop
+        };
+
+        FilterConfig {
+            tokens: args.tokens,
+            token_match,
+            min_value: args.min_value,
+        }
+    }
+}
+
+// RFC 3339, `2026-10-01T00:00Z` style times without seconds, plain dates or
+// unix seconds. Times without an offset are read as UTC.
+fn parse_timestamp(s: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a UTC date time, got \"{}\"", s);
+
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(seconds);
+    }
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(s) {
+        return u64::try_from(date_time.timestamp()).map_err(|_| invalid());
+    }
+
+    let utc = s.trim_end_matches('Z');
+    let date_time = [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(utc, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(utc, "%Y-%m-%d")
+            .ok()
+            .map(|date| date.and_hms(0, 0, 0))
+    })
+    .ok_or_else(invalid)?;
+    u64::try_from(date_time.timestamp()).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_utc_date_times() {
+        assert_eq!(parse_timestamp("2026-10-01T00:00Z"), Ok(1790812800));
+        assert_eq!(parse_timestamp("2026-10-01T00:00:00Z"), Ok(1790812800));
+        assert_eq!(parse_timestamp("2026-10-01 00:00"), Ok(1790812800));
+        assert_eq!(parse_timestamp("2026-10-01T02:00:00+02:00"), Ok(1790812800));
+    }
+
+    #[test]
+    fn parses_plain_dates_as_midnight_utc() {
+        assert_eq!(parse_timestamp("2026-10-01"), Ok(1790812800));
+    }
+
+    #[test]
+    fn parses_unix_seconds() {
+        assert_eq!(parse_timestamp("1790812800"), Ok(1790812800));
+    }
+
+    #[test]
+    fn rejects_other_timestamps() {
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("2026-13-01").is_err());
+        assert!(parse_timestamp("-1").is_err());
+    }
+
+    #[test]
+    fn parses_block_ranges() {
+        let BlockRange(from, to) = "17000000..17000100".parse().unwrap();
+        assert_eq!((from, to), (17000000, 17000100));
+        let BlockRange(from, to) = " 1 .. 2 ".parse().unwrap();
+        assert_eq!((from, to), (1, 2));
+    }
+
+    #[test]
+    fn rejects_bad_block_ranges() {
+        for range in ["17000000", "17000000..", "..17000100", "a..b", "1...2"] {
+            assert_eq!(
+                range.parse::<BlockRange>().err(),
+                Some(format!("expected A..B, got \"{}\"", range))
+            );
+        }
     }
 }
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use anyhow::Context;
+use token_list::Token;
+
+use crate::{
+    config::{FeedConfig, RevertedMode},
+    provider::TxnDetails,
+    records::{ActionRecord, AmountRecord, TokenRecord, TxnRecord, TxnStatus},
+    uni_helpers::DecodedTxn,
+};
+
+/// Columns of `--output csv`, in order. Only ever appended to.
+const CSV_HEADER: &[&str] = &[
+    "block_number",
+    "tx_hash",
+    "from",
+    "method",
+    "status",
+    "exact_input",
+    "fee_on_transfer",
+    "recipient",
+    "path",
+    "origin_token",
+    "origin_symbol",
+    "origin_amount_raw",
+    "origin_amount_formatted",
+    "destination_token",
+    "destination_symbol",
+    "destination_amount_raw",
+    "destination_amount_formatted",
+    "executed_in_raw",
+    "executed_in_formatted",
+    "executed_out_raw",
+    "executed_out_formatted",
+    "gas_cost_eth",
+    "priority_tip_wei",
+    "revert_reason",
+];
+
+const STATUS_COLUMN: usize = 4;
+
+/// Writes one row per decoded swap to `--out-file`. Liquidity changes and
+/// calldata that didn't decode have no row.
+pub struct CsvSink {
+    writer: csv::Writer<File>,
+}
+
+impl CsvSink {
+    /// Truncates `path` and writes the header row
+    pub fn create(path: &Path) -> anyhow::Result<CsvSink> {
+        let mut writer = csv::Writer::from_path(path)
+            .with_context(|| format!("Can't create {}", path.display()))?;
+        writer.write_record(CSV_HEADER)?;
+        writer.flush()?;
+
+        Ok(CsvSink { writer })
+    }
+
+    /// Keeps the rows already in `path`, only writing the header to an empty
+    /// file. Used when resuming.
+    pub fn append(path: &Path) -> anyhow::Result<CsvSink> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Can't open {}", path.display()))?;
+        let is_empty = file.metadata()?.len() == 0;
+
+        let mut writer = csv::Writer::from_writer(file);
+        if is_empty {
+            writer.write_record(CSV_HEADER)?;
+            writer.flush()?;
+        }
+
+        Ok(CsvSink { writer })
+    }
+
+    /// Flushes once the block is written so the file can be read while the
+    /// listener runs
+    pub fn write_txns(
+        &mut self,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> anyhow::Result<()> {
+        self.write_rows(decoded, details, token_map, feed_config, None)
+    }
+
+    /// Writes the swaps of a block that was reorganized out again, with
+    /// `reorged` as their status
+    pub fn revert_txns(
+        &mut self,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> anyhow::Result<()> {
+        self.write_rows(decoded, details, token_map, feed_config, Some("reorged"))
+    }
+
+    fn write_rows(
+        &mut self,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+        status: Option<&str>,
+    ) -> anyhow::Result<()> {
+        for (txn, call_data) in decoded {
+            let record = TxnRecord::new(txn, call_data, details, token_map, feed_config);
+            if record.status == Some(TxnStatus::Reverted)
+                && feed_config.reverted == RevertedMode::Hide
+            {
+                continue;
+            }
+            if let Some(mut row) = swap_row(&record) {
+                if let Some(status) = status {
+                    row[STATUS_COLUMN] = status.to_string();
+                }
+                self.writer.write_record(&row)?;
+            }
+        }
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+fn swap_row(record: &TxnRecord) -> Option<Vec<String>> {
+    let (exact_input, fee_on_transfer, recipient, path, origin, destination) = match &record.action
+    {
+        Some(ActionRecord::Swap {
+            exact_input,
+            fee_on_transfer,
+            recipient,
+            path,
+            origin,
+            destination,
+        }) => (
+            exact_input,
+            fee_on_transfer,
+            recipient,
+            path,
+            origin,
+            destination,
+        ),
+        _ => return None,
+    };
+
+    let (executed_in, executed_out) = match &record.executed {
+        Some(executed) => (
+            executed.hops.first().map(|hop| &hop.amount_in),
+            executed
+                .received
+                .as_ref()
+                .or_else(|| executed.hops.last().map(|hop| &hop.amount_out)),
+        ),
+        None => (None, None),
+    };
+    let [origin_raw, origin_formatted] = amount_columns(Some(origin));
+    let [destination_raw, destination_formatted] = amount_columns(Some(destination));
+    let [executed_in_raw, executed_in_formatted] = amount_columns(executed_in);
+    let [executed_out_raw, executed_out_formatted] = amount_columns(executed_out);
+    let gas = record.gas.as_ref();
+
+    Some(vec![
+        record
+            .block_number
+            .map(|number| number.to_string())
+            .unwrap_or_default(),
+        format!("{:?}", record.tx_hash),
+        format!("{:?}", record.from),
+        record.method.clone(),
+        match record.status {
+            Some(TxnStatus::Success) => "success".to_string(),
+            Some(TxnStatus::Reverted) => "reverted".to_string(),
+            None => String::new(),
+        },
+        exact_input.to_string(),
+        fee_on_transfer.to_string(),
+        format!("{:?}", recipient),
+        path.iter()
+            .map(token_str)
+            .collect::<Vec<String>>()
+            .join(">"),
+        address_str(&origin.token),
+        origin.token.symbol.clone().unwrap_or_default(),
+        origin_raw,
+        origin_formatted,
+        address_str(&destination.token),
+        destination.token.symbol.clone().unwrap_or_default(),
+        destination_raw,
+        destination_formatted,
+        executed_in_raw,
+        executed_in_formatted,
+        executed_out_raw,
+        executed_out_formatted,
+        // ETH decimals are always known
+        gas.and_then(|gas| gas.cost.as_ref())
+            .and_then(|cost| cost.formatted.clone())
+            .unwrap_or_default(),
+        gas.and_then(|gas| gas.priority_tip.clone())
+            .unwrap_or_default(),
+        record.revert_reason.clone().unwrap_or_default(),
+    ])
+}
+
+// base units, then the decimal amount, left empty when the decimals are
+// unknown so a column never mixes the two
+fn amount_columns(amount: Option<&AmountRecord>) -> [String; 2] {
+    match amount {
+        Some(amount) => [
+            amount.raw.clone(),
+            amount.formatted.clone().unwrap_or_default(),
+        ],
+        None => [String::new(), String::new()],
+    }
+}
+
+// empty for ETH
+fn address_str(token: &TokenRecord) -> String {
+    token
+        .address
+        .map(|address| format!("{:?}", address))
+        .unwrap_or_default()
+}
+
+fn token_str(token: &TokenRecord) -> String {
+    match &token.symbol {
+        Some(symbol) => symbol.clone(),
+        None => address_str(token),
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use ethers::core::abi::Token as AbiToken;
+use ethers::prelude::*;
+use ethers::utils::hex;
+use paris::Logger;
+use token_list::Token;
+
+use crate::{
+    amount_format::AmountFormat,
+    config::{FeedConfig, OutputFormat},
+    execution::SwapExecution,
+    gas::GasCost,
+    provider::{get_revert_reason, is_reverted, TxnDetails},
+    records::write_jsonl,
+    slippage::{get_quotes, Slippage},
+    uni_helpers::{
+        build_side_str, decode_txns, router_method_name, UniRouterAction, UniTxnInputs,
+        UNISWAP_ADDR_STR,
+    },
+    uni_v2_router::{UniV2Router, UNIV2ROUTER_ABI},
+};
+
+/// Fetches a single transaction with its receipt and prints everything known
+/// about it, one field per line. Errors once printed if the transaction isn't
+/// a router call that decodes, so scripts can rely on the exit code.
+pub async fn decode_txn(
+    client: &Provider<Http>,
+    router: &UniV2Router<Provider<Http>>,
+    tx_hash: TxHash,
+    token_map: &HashMap<String, Token>,
+    feed_config: &FeedConfig,
+) -> anyhow::Result<()> {
+    if feed_config.output == OutputFormat::Csv {
+        bail!("decode prints to stdout, use --output text or jsonl");
+    }
+
+    let mut logger = Logger::new();
+    let amount_format = &feed_config.amount_format;
+
+    let txn = match client.get_transaction(tx_hash).await? {
+        Some(txn) => txn,
+        None => bail!("Txn {} wasn't found", tx_hash),
+    };
+    let receipt = client.get_transaction_receipt(tx_hash).await?;
+    let block = match txn.block_number {
+        Some(number) => client.get_block(number).await?,
+        None => None,
+    };
+
+    if feed_config.output == OutputFormat::Jsonl {
+        let base_fee = block.and_then(|block| block.base_fee_per_gas);
+        return write_txn_record(
+            client,
+            router,
+            txn,
+            receipt,
+            base_fee,
+            token_map,
+            feed_config,
+        )
+        .await;
+    }
+
+    logger.info(format!("Txn {}", txn.hash));
+    let mut field = |label: &str, value: String| {
+        logger.indent(1).log(format!("{:<10} {}", label, value));
+    };
+
+    field(
+        "Block",
+        match (&block, txn.transaction_index) {
+            (Some(block), Some(index)) => format!(
+                "{} ({}), index {}",
+                block.number.unwrap_or_default(),
+                block.hash.unwrap_or_default(),
+                index
+            ),
+            _ => "pending".to_string(),
+        },
+    );
+    field("From", format!("{:?}", txn.from));
+    field(
+        "To",
+        txn.to
+            .map_or("contract creation".to_string(), |to| format!("{:?}", to)),
+    );
+    field(
+        "Value",
+        build_side_str(&txn.value, &None, token_map, amount_format),
+    );
+    field("Method", router_method_name(&txn.input));
+
+    let router_address = UNISWAP_ADDR_STR
+        .parse::<Address>()
+        .expect("Can't parse string to H160");
+    if txn.to != Some(router_address) {
+        bail!("Txn {} isn't sent to the Uniswap V2 router", txn.hash);
+    }
+
+    let decoded = decode_txns(&[&txn]);
+    let call_data = match &decoded[0].1 {
+        Ok(call_data) => call_data,
+        Err(err) => bail!("Txn {} can't be decoded, {}", txn.hash, err),
+    };
+    field("Action", call_data.log_str(token_map, amount_format));
+    if let UniRouterAction::Swap(inputs) = call_data {
+        for (label, value) in swap_fields(inputs, token_map, amount_format) {
+            field(label, value);
+        }
+    }
+
+    let receipt = match receipt {
+        Some(receipt) => receipt,
+        None => {
+            field("Status", "pending".to_string());
+            return Ok(());
+        }
+    };
+
+    if is_reverted(&receipt) {
+        let status = match get_revert_reason(client, &txn).await {
+            Some(reason) => format!("REVERTED ({})", reason),
+            None => "REVERTED".to_string(),
+        };
+        field("Status", status);
+    } else {
+        field("Status", "success".to_string());
+    }
+
+    if let UniRouterAction::Swap(inputs) = call_data {
+        if let Some(execution) = SwapExecution::from_receipt(inputs, &receipt) {
+            field("Executed", execution.log_str(token_map, amount_format));
+            let quotes = get_quotes(router, &decoded).await;
+            if let Some(slippage) = quotes
+                .get(&txn.hash)
+                .and_then(|quote| Slippage::new(inputs, &execution, *quote))
+            {
+                field("Slippage", slippage.log_str());
+            }
+        }
+    }
+
+    let base_fee = block.and_then(|block| block.base_fee_per_gas);
+    let gas_cost = GasCost::new(&txn, Some(&receipt), base_fee);
+    field(
+        "Gas",
+        gas_cost.log_str(amount_format, feed_config.eth_usd_price),
+    );
+    field(
+        "Gas used",
+        format!(
+            "{} of {} limit",
+            receipt.gas_used.unwrap_or_default(),
+            txn.gas
+        ),
+    );
+
+    Ok(())
+}
+
+// the jsonl flavour of `decode_txn`, one record with everything fetched
+async fn write_txn_record(
+    client: &Provider<Http>,
+    router: &UniV2Router<Provider<Http>>,
+    txn: Transaction,
+    receipt: Option<TransactionReceipt>,
+    base_fee: Option<U256>,
+    token_map: &HashMap<String, Token>,
+    feed_config: &FeedConfig,
+) -> anyhow::Result<()> {
+    let decoded = decode_txns(&[&txn]);
+
+    let mut details = TxnDetails {
+        base_fee,
+        ..Default::default()
+    };
+    if let Some(receipt) = receipt {
+        if is_reverted(&receipt) {
+            if let Some(reason) = get_revert_reason(client, &txn).await {
+                details.revert_reasons.insert(txn.hash, reason);
+            }
+        }
+        details.receipts.insert(txn.hash, receipt);
+    }
+    details.quotes = get_quotes(router, &decoded).await;
+
+    write_jsonl(&decoded, &details, token_map, feed_config);
+
+    let router_address = UNISWAP_ADDR_STR
+        .parse::<Address>()
+        .expect("Can't parse string to H160");
+    if txn.to != Some(router_address) {
+        bail!("Txn {} isn't sent to the Uniswap V2 router", txn.hash);
+    }
+    if let Err(err) = &decoded[0].1 {
+        bail!("Txn {} can't be decoded, {}", txn.hash, err);
+    }
+
+    Ok(())
+}
+
+fn swap_fields(
+    inputs: &UniTxnInputs,
+    token_map: &HashMap<String, Token>,
+    amount_format: &AmountFormat,
+) -> Vec<(&'static str, String)> {
+    let origin_str = build_side_str(
+        &inputs.origin_amount(),
+        &inputs.origin_address(),
+        token_map,
+        amount_format,
+    );
+    let destination_str = build_side_str(
+        &inputs.destination_amount(),
+        &inputs.destination_address(),
+        token_map,
+        amount_format,
+    );
+
+    // the side the trader didn't fix is the slippage bound
+    let (sells, buys, kind) = if inputs.exact_input() {
+        (
+            origin_str,
+            format!("at least {}", destination_str),
+            "exact input",
+        )
+    } else {
+        (
+            format!("at most {}", origin_str),
+            destination_str,
+            "exact output",
+        )
+    };
+    let kind = if inputs.fee_on_transfer() {
+        format!("{}, fee-on-transfer", kind)
+    } else {
+        kind.to_string()
+    };
+
+    vec![
+        ("Kind", kind),
+        ("Path", inputs.path_str(token_map)),
+        ("Sells", sells),
+        ("Buys", buys),
+        ("Recipient", format!("{:?}", inputs.recipient())),
+    ]
+}
+
+/// Decodes raw router calldata without touching a node. Prints the matching
+/// router function with every named argument, then the decoded action. Token
+/// amounts render with their list decimals when `token_map` knows them.
+pub fn decode_calldata(
+    calldata: &str,
+    value: U256,
+    token_map: &HashMap<String, Token>,
+    amount_format: &AmountFormat,
+) -> anyhow::Result<()> {
+    let mut logger = Logger::new();
+
+    let calldata: String = calldata.chars().filter(|c| !c.is_whitespace()).collect();
+    let input = match hex::decode(calldata.trim_start_matches("0x")) {
+        Ok(input) => Bytes::from(input),
+        Err(err) => bail!("Calldata isn't valid hex, {}", err),
+    };
+
+    let call_data = UniRouterAction::try_from_calldata(&input, value);
+
+    logger.info(format!("Call {}", router_method_name(&input)));
+    if let Some(function) = UNIV2ROUTER_ABI
+        .functions()
+        .find(|function| input.len() >= 4 && function.short_signature() == input[..4])
+    {
+        if let Ok(args) = function.decode_input(&input[4..]) {
+            for (param, arg) in function.inputs.iter().zip(args.iter()) {
+                logger.indent(1).log(format!(
+                    "{} ({}) {}",
+                    param.name,
+                    param.kind,
+                    format_abi_token(arg)
+                ));
+            }
+        }
+    }
+    if !value.is_zero() {
+        logger.indent(1).log(format!(
+            "value {}",
+            build_side_str(&value, &None, token_map, amount_format)
+        ));
+    }
+
+    match call_data {
+        Ok(call_data) => {
+            logger.indent(1).log(format!(
+                "Action {}",
+                call_data.log_str(token_map, amount_format)
+            ));
+            Ok(())
+        }
+        Err(err) => bail!("Calldata can't be decoded, {}", err),
+    }
+}
+
+// `Token`'s own `Display` prints numbers in hex and addresses without a prefix
+fn format_abi_token(token: &AbiToken) -> String {
+    match token {
+        AbiToken::Address(address) => format!("{:?}", address),
+        AbiToken::Uint(number) | AbiToken::Int(number) => number.to_string(),
+        AbiToken::Bool(flag) => flag.to_string(),
+        AbiToken::String(string) => format!("{:?}", string),
+        AbiToken::Bytes(bytes) | AbiToken::FixedBytes(bytes) => {
+            format!("0x{}", hex::encode(bytes))
+        }
+        AbiToken::Array(tokens) | AbiToken::FixedArray(tokens) => format!(
+            "[{}]",
+            tokens
+                .iter()
+                .map(format_abi_token)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        AbiToken::Tuple(tokens) => format!(
+            "({})",
+            tokens
+                .iter()
+                .map(format_abi_token)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use ethers::prelude::*;
+use token_list::Token;
+
+use crate::{
+    amount_format::AmountFormat,
+    uni_helpers::{build_side_str, pair_for, UniTxnInputs, UNISWAP_ADDR_STR},
+    uni_v2_pair::{decode_log, SwapFilter, TransferFilter},
+};
+
+/// What a single pair in the route actually swapped
+pub struct HopExecution {
+    pub pair: Address,
+    /// position of the pair's `Swap` event in the block
+    pub log_index: Option<u64>,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub amount_in: U256,
+    pub amount_out: U256,
+}
+
+/// Amounts a swap executed with, read from the pair events in its receipt
+pub struct SwapExecution {
+    pub hops: Vec<HopExecution>,
+    /// tokens that reached the recipient, below the last hop's output when the
+    /// destination token takes a fee on transfer. `None` for ETH payouts.
+    pub received: Option<U256>,
+}
+
+impl SwapExecution {
+    /// `None` if a hop's pair has no `Swap` passing its output along the
+    /// path, which is the case for reverted transactions. Every hop is
+    /// matched to the `Swap` of its own pair, so other pairs and the taxes a
+    /// fee-on-transfer token sells while being transferred are left out.
+    pub fn from_receipt(
+        inputs: &UniTxnInputs,
+        receipt: &TransactionReceipt,
+    ) -> Option<SwapExecution> {
+        let path = inputs.path();
+        if path.len() < 2 {
+            return None;
+        }
+        let pairs: Vec<Address> = path
+            .windows(2)
+            .map(|tokens| pair_for(tokens[0], tokens[1]))
+            .collect();
+        // the router unwraps WETH itself before paying out ETH
+        let payee = match inputs.destination_address() {
+            Some(_) => inputs.recipient(),
+            None => UNISWAP_ADDR_STR
+                .parse::<Address>()
+                .expect("Can't parse string to H160"),
+        };
+
+        // each pair pays the next one, so hops are walked back from the last,
+        // taking the latest matching `Swap` before the next hop's. Tax sales
+        // on the first pair happen while the router pays it, before its swap.
+        let mut hops = Vec::with_capacity(pairs.len());
+        let mut before = receipt.logs.len();
+        for (hop, tokens) in path.windows(2).enumerate().rev() {
+            let to = pairs.get(hop + 1).copied().unwrap_or(payee);
+            let (position, log, swap) = receipt.logs[..before]
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, log)| log.address == pairs[hop])
+                .filter_map(|(position, log)| {
+                    decode_log::<SwapFilter>(log).map(|swap| (position, log, swap))
+                })
+                .find(|(_, _, swap)| swap.to == to)?;
+            before = position;
+
+            let (token_in, token_out) = (tokens[0], tokens[1]);
+            // pairs order their tokens by address, token0 being the lower one
+            let (amount_in, amount_out) = if token_in < token_out {
+                (swap.amount_0_in, swap.amount_1_out)
+            } else {
+                (swap.amount_1_in, swap.amount_0_out)
+            };
+            hops.push(HopExecution {
+                pair: log.address,
+                log_index: log.log_index.map(|index| index.as_u64()),
+                token_in,
+                token_out,
+                amount_in,
+                amount_out,
+            });
+        }
+        hops.reverse();
+
+        let received = inputs.destination_address().and_then(|token| {
+            let transfers: Vec<TransferFilter> = receipt
+                .logs
+                .iter()
+                .filter(|log| log.address == token)
+                .filter_map(decode_log::<TransferFilter>)
+                .filter(|transfer| transfer.to == inputs.recipient())
+                .collect();
+            if transfers.is_empty() {
+                None
+            } else {
+                Some(
+                    transfers
+                        .iter()
+                        .fold(U256::zero(), |total, transfer| total + transfer.value),
+                )
+            }
+        });
+
+        Some(SwapExecution { hops, received })
+    }
+
+    /// what the first pair was paid
+    pub fn amount_in(&self) -> U256 {
+        self.hops
+            .first()
+            .expect("executions have at least one hop")
+            .amount_in
+    }
+
+    /// what reached the recipient, falling back to the last pair's output
+    /// for ETH payouts
+    pub fn amount_out(&self) -> U256 {
+        self.received.unwrap_or_else(|| {
+            self.hops
+                .last()
+                .expect("executions have at least one hop")
+                .amount_out
+        })
+    }
+
+    pub fn log_str(
+        &self,
+        token_map: &HashMap<String, Token>,
+        amount_format: &AmountFormat,
+    ) -> String {
+        let hops_str = self
+            .hops
+            .iter()
+            .map(|hop| {
+                format!(
+                    "{} ➜ {}",
+                    build_side_str(
+                        &hop.amount_in,
+                        &Some(hop.token_in),
+                        token_map,
+                        amount_format
+                    ),
+                    build_side_str(
+                        &hop.amount_out,
+                        &Some(hop.token_out),
+                        token_map,
+                        amount_format
+                    )
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let last_hop = self.hops.last().expect("executions have at least one hop");
+        match self.received {
+            Some(received) if received != last_hop.amount_out => format!(
+                "Executed {} (received {})",
+                hops_str,
+                build_side_str(
+                    &received,
+                    &Some(last_hop.token_out),
+                    token_map,
+                    amount_format
+                )
+            ),
+            _ => format!("Executed {}", hops_str),
+        }
+    }
+}
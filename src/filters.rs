@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use anyhow::bail;
+use ethers::prelude::*;
+use futures::future::join_all;
+use token_list::Token;
+
+use crate::{
+    config::FilterConfig,
+    uni_helpers::{DecodedTxn, UniRouterAction, UniTxnInputs, USDC_ADDR_STR, WETH_ADDR_STR},
+    uni_v2_router::UniV2Router,
+};
+
+/// Where in a swap path a filtered token has to show up
+#[derive(Clone, Copy, PartialEq)]
+pub enum TokenMatch {
+    /// anywhere in the path, intermediate hops included
+    AnyHop,
+    /// only as the token sold or the token bought
+    EndpointsOnly,
+}
+
+/// Keeps the swaps that route through a set of tokens
+pub struct TokenFilter {
+    addresses: HashSet<Address>,
+    token_match: TokenMatch,
+}
+
+impl TokenFilter {
+    /// Resolves each of `tokens`, a symbol or a `0x` address, against the token
+    /// list. Symbols match case insensitively and keep every listed token that
+    /// shares them. `ETH` resolves to WETH since swaps route ETH through it.
+    pub fn new(
+        tokens: &[String],
+        token_match: TokenMatch,
+        token_map: &HashMap<String, Token>,
+    ) -> anyhow::Result<TokenFilter> {
+        let mut addresses = HashSet::new();
+        for token in tokens {
+            let resolved = resolve_token(token, token_map);
+            if resolved.is_empty() {
+                bail!(
+                    "--token {} isn't an address or a symbol in the token list",
+                    token
+                );
+            }
+            addresses.extend(resolved);
+        }
+
+        Ok(TokenFilter {
+            addresses,
+            token_match,
+        })
+    }
+
+    pub fn matches(&self, inputs: &UniTxnInputs) -> bool {
+        let path = inputs.path();
+        match self.token_match {
+            TokenMatch::AnyHop => path.iter().any(|token| self.addresses.contains(token)),
+            TokenMatch::EndpointsOnly => path
+                .first()
+                .into_iter()
+                .chain(path.last())
+                .any(|token| self.addresses.contains(token)),
+        }
+    }
+
+    /// Drops everything but matching swaps, liquidity changes and calldata
+    /// that didn't decode included
+    pub fn apply<'a>(&self, decoded: Vec<DecodedTxn<'a>>) -> Vec<DecodedTxn<'a>> {
+        decoded
+            .into_iter()
+            .filter(|(_, call_data)| match call_data {
+                Ok(UniRouterAction::Swap(inputs)) => self.matches(inputs),
+                _ => false,
+            })
+            .collect()
+    }
+}
+
+fn resolve_token(token: &str, token_map: &HashMap<String, Token>) -> Vec<Address> {
+    if let Ok(address) = token.parse::<Address>() {
+        return vec![address];
+    }
+
+    let symbol = if token.eq_ignore_ascii_case("ETH") {
+        "WETH"
+    } else {
+        token
+    };
+    token_map
+        .values()
+        .filter(|t| t.symbol.eq_ignore_ascii_case(symbol))
+        .filter_map(|t| t.address.parse::<Address>().ok())
+        .collect()
+}
+
+const USDC_DECIMALS: i32 = 6;
+
+/// Smallest swap worth printing, from `--min-value` such as `5 ETH` or `10k USD`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinValue {
+    Eth(f64),
+    Usd(f64),
+}
+
+impl FromStr for MinValue {
+    type Err = String;
+
+    /// Amounts take an optional `k`/`m` multiplier and default to ETH without a unit
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.trim().to_uppercase();
+        let (amount, usd) = if let Some(amount) = upper.strip_suffix("USD") {
+            (amount.trim(), true)
+        } else {
+            (upper.strip_suffix("ETH").unwrap_or(&upper).trim(), false)
+        };
+
+        let (amount, multiplier) = match amount.chars().last() {
+            Some('K') => (&amount[..amount.len() - 1], 1e3),
+            Some('M') => (&amount[..amount.len() - 1], 1e6),
+            _ => (amount, 1.0),
+        };
+        let amount = amount
+            .parse::<f64>()
+            .map_err(|_| format!("can't read an amount from \"{}\"", s))?
+            * multiplier;
+        if amount < 0.0 {
+            return Err(format!("\"{}\" is negative", s));
+        }
+
+        Ok(if usd {
+            MinValue::Usd(amount)
+        } else {
+            MinValue::Eth(amount)
+        })
+    }
+}
+
+/// Keeps the swaps worth at least `min_value`. Each swap is valued in ETH at
+/// the parent block: through its WETH end when the path has one, otherwise by
+/// quoting one of its ends into WETH with the router.
+pub struct ValueFilter {
+    min_value: MinValue,
+    /// fixed ETH price for USD thresholds, quoted from WETH/USDC when unset
+    eth_usd_price: Option<f64>,
+}
+
+impl ValueFilter {
+    pub fn new(min_value: MinValue, eth_usd_price: Option<f64>) -> ValueFilter {
+        ValueFilter {
+            min_value,
+            eth_usd_price,
+        }
+    }
+
+    /// Drops swaps below the threshold or that can't be valued, as well as
+    /// anything that isn't a swap. Fails if a USD threshold can't be
+    /// converted to ETH, rather than letting the block through unfiltered.
+    pub async fn apply<'a, M: Middleware>(
+        &self,
+        router: &UniV2Router<M>,
+        block_number: U64,
+        decoded: Vec<DecodedTxn<'a>>,
+    ) -> anyhow::Result<Vec<DecodedTxn<'a>>> {
+        let parent_block = block_number - 1_u64;
+        let min_wei = match self.min_wei(router, parent_block).await {
+            Some(min_wei) => min_wei,
+            None => bail!(
+                "Can't quote ETH in USD at block {} for --min-value",
+                parent_block
+            ),
+        };
+
+        let values = join_all(decoded.iter().map(|(_, call_data)| async move {
+            match call_data {
+                Ok(UniRouterAction::Swap(inputs)) => {
+                    value_in_eth(router, parent_block, inputs).await
+                }
+                _ => None,
+            }
+        }))
+        .await;
+
+        Ok(decoded
+            .into_iter()
+            .zip(values)
+            .filter(|(_, value)| value.map_or(false, |value| value >= min_wei))
+            .map(|(decoded, _)| decoded)
+            .collect())
+    }
+
+    async fn min_wei<M: Middleware>(
+        &self,
+        router: &UniV2Router<M>,
+        parent_block: U64,
+    ) -> Option<U256> {
+        let min_eth = match self.min_value {
+            MinValue::Eth(eth) => eth,
+            MinValue::Usd(usd) => {
+                let eth_usd_price = match self.eth_usd_price {
+                    Some(price) => price,
+                    None => get_eth_usd_price(router, parent_block).await?,
+                };
+                usd / eth_usd_price
+            }
+        };
+        Some(U256::from((min_eth * 1e18) as u128))
+    }
+}
+
+/// Every filter set on the command line, applied to a block's decoded txns
+#[derive(Default)]
+pub struct Filters {
+    pub token: Option<TokenFilter>,
+    pub value: Option<ValueFilter>,
+}
+
+impl Filters {
+    /// Resolves the filters set on the command line, `eth_usd_price` converts
+    /// USD thresholds when set
+    pub fn from_config(
+        filter_config: &FilterConfig,
+        eth_usd_price: Option<f64>,
+        token_map: &HashMap<String, Token>,
+    ) -> anyhow::Result<Filters> {
+        let token = if filter_config.tokens.is_empty() {
+            None
+        } else {
+            Some(TokenFilter::new(
+                &filter_config.tokens,
+                filter_config.token_match,
+                token_map,
+            )?)
+        };
+        let value = filter_config
+            .min_value
+            .map(|min_value| ValueFilter::new(min_value, eth_usd_price));
+
+        Ok(Filters { token, value })
+    }
+
+    pub async fn apply<'a, M: Middleware>(
+        &self,
+        router: &UniV2Router<M>,
+        block_number: U64,
+        mut decoded: Vec<DecodedTxn<'a>>,
+    ) -> anyhow::Result<Vec<DecodedTxn<'a>>> {
+        // the token filter is free, run it first to save quotes
+        if let Some(token) = &self.token {
+            decoded = token.apply(decoded);
+        }
+        if let Some(value) = &self.value {
+            if !decoded.is_empty() {
+                decoded = value.apply(router, block_number, decoded).await?;
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+async fn value_in_eth<M: Middleware>(
+    router: &UniV2Router<M>,
+    parent_block: U64,
+    inputs: &UniTxnInputs,
+) -> Option<U256> {
+    let weth = weth_address();
+    let path = inputs.path();
+    let (first, last) = (*path.first()?, *path.last()?);
+
+    if first == weth {
+        return Some(inputs.origin_amount());
+    }
+    if last == weth {
+        return Some(inputs.destination_amount());
+    }
+
+    // tokens without a WETH pair fail to quote, try the other end then
+    match quote(
+        router,
+        parent_block,
+        inputs.origin_amount(),
+        vec![first, weth],
+    )
+    .await
+    {
+        Some(value) => Some(value),
+        None => {
+            quote(
+                router,
+                parent_block,
+                inputs.destination_amount(),
+                vec![last, weth],
+            )
+            .await
+        }
+    }
+}
+
+async fn get_eth_usd_price<M: Middleware>(
+    router: &UniV2Router<M>,
+    parent_block: U64,
+) -> Option<f64> {
+    let usdc = USDC_ADDR_STR
+        .parse::<Address>()
+        .expect("Can't parse USDC address");
+    let one_eth = U256::exp10(18);
+    let usdc_out = quote(router, parent_block, one_eth, vec![weth_address(), usdc]).await?;
+    Some(usdc_out.low_u128() as f64 / 10_f64.powi(USDC_DECIMALS))
+}
+
+async fn quote<M: Middleware>(
+    router: &UniV2Router<M>,
+    parent_block: U64,
+    amount_in: U256,
+    path: Vec<Address>,
+) -> Option<U256> {
+    let amounts = router
+        .get_amounts_out(amount_in, path)
+        .block(parent_block)
+        .call()
+        .await
+        .ok()?;
+    amounts.last().copied()
+}
+
+fn weth_address() -> Address {
+    WETH_ADDR_STR
+        .parse::<Address>()
+        .expect("Can't parse WETH address")
+}
@@ -0,0 +1,125 @@
+use ethers::prelude::*;
+use serde::Serialize;
+
+use crate::amount_format::{format_amount, AmountFormat};
+
+const GWEI_DECIMALS: usize = 9;
+const ETH_DECIMALS: usize = 18;
+
+/// Envelope the transaction was sent in, from its `type` field
+#[derive(Serialize, Clone, Copy, PartialEq)]
+pub enum TxnType {
+    #[serde(rename = "legacy")]
+    Legacy,
+    /// EIP-2930, legacy pricing plus an access list
+    #[serde(rename = "eip2930")]
+    AccessList,
+    /// EIP-1559, base fee plus a priority tip
+    #[serde(rename = "eip1559")]
+    Eip1559,
+}
+
+impl TxnType {
+    pub fn from_txn(txn: &Transaction) -> TxnType {
+        match txn.transaction_type.map(|t| t.as_u64()) {
+            Some(1) => TxnType::AccessList,
+            Some(2) => TxnType::Eip1559,
+            _ => TxnType::Legacy,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TxnType::Legacy => "legacy",
+            TxnType::AccessList => "EIP-2930",
+            TxnType::Eip1559 => "EIP-1559",
+        }
+    }
+}
+
+/// What a transaction paid for gas
+pub struct GasCost {
+    pub txn_type: TxnType,
+    /// `gas_used * effective_gas_price` in wei, `None` without a receipt
+    pub cost: Option<U256>,
+    /// per gas paid to the block builder on top of the base fee, in wei.
+    /// Without a receipt this is the most the transaction was willing to tip.
+    pub priority_tip: Option<U256>,
+}
+
+impl GasCost {
+    /// `base_fee` is the including block's `base_fee_per_gas`, `None` before London
+    pub fn new(
+        txn: &Transaction,
+        receipt: Option<&TransactionReceipt>,
+        base_fee: Option<U256>,
+    ) -> GasCost {
+        let txn_type = TxnType::from_txn(txn);
+
+        let effective_gas_price = receipt
+            .and_then(|receipt| receipt.effective_gas_price)
+            .or_else(|| effective_gas_price(txn, txn_type, base_fee));
+
+        let cost = receipt
+            .and_then(|receipt| receipt.gas_used)
+            .zip(effective_gas_price)
+            .map(|(gas_used, gas_price)| gas_used.saturating_mul(gas_price));
+
+        let priority_tip = effective_gas_price
+            .map(|gas_price| gas_price.saturating_sub(base_fee.unwrap_or_default()));
+
+        GasCost {
+            txn_type,
+            cost,
+            priority_tip,
+        }
+    }
+
+    pub fn cost_usd(&self, eth_usd_price: Option<f64>) -> Option<f64> {
+        Some(wei_to_eth(self.cost?) * eth_usd_price?)
+    }
+
+    /// `eth_usd_price` adds the cost in USD next to the ETH amount
+    pub fn log_str(&self, amount_format: &AmountFormat, eth_usd_price: Option<f64>) -> String {
+        let mut parts = vec![];
+        if let Some(cost) = self.cost {
+            let mut cost_str = format!("{} ETH", format_amount(&cost, ETH_DECIMALS, amount_format));
+            if let Some(cost_usd) = self.cost_usd(eth_usd_price) {
+                cost_str.push_str(&format!(" (${:.2})", cost_usd));
+            }
+            parts.push(cost_str);
+        }
+        if let Some(priority_tip) = self.priority_tip {
+            parts.push(format!(
+                "tip {} gwei",
+                format_amount(&priority_tip, GWEI_DECIMALS, amount_format)
+            ));
+        }
+        parts.push(self.txn_type.label().to_string());
+
+        format!("Gas {}", parts.join(", "))
+    }
+}
+
+// what the transaction pays per gas before its receipt is known. Legacy
+// transactions pay their gas price, EIP-1559 ones the base fee plus a tip
+// capped by `max_fee_per_gas`.
+fn effective_gas_price(
+    txn: &Transaction,
+    txn_type: TxnType,
+    base_fee: Option<U256>,
+) -> Option<U256> {
+    match (txn_type, base_fee) {
+        (TxnType::Eip1559, Some(base_fee)) => {
+            let max_fee = txn.max_fee_per_gas?;
+            let max_priority_fee = txn.max_priority_fee_per_gas?;
+            Some(max_fee.min(base_fee.saturating_add(max_priority_fee)))
+        }
+        _ => txn.gas_price,
+    }
+}
+
+// USD figures only need cents, the precision lost to f64 doesn't show
+fn wei_to_eth(wei: U256) -> f64 {
+    wei.low_u128() as f64 / 1e18
+}
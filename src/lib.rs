@@ -1,7 +1,25 @@
+pub mod amount_format;
+pub mod backfill;
+pub mod checkpoint;
 pub mod config;
+pub mod csv_sink;
+pub mod decode;
+pub mod execution;
+pub mod filters;
+pub mod gas;
 pub mod logging;
+pub mod pipeline;
+pub mod postgres_sink;
 pub mod provider;
+pub mod records;
+pub mod reorg;
+pub mod sinks;
+pub mod slippage;
+pub mod sqlite_sink;
+pub mod time_machine;
 pub mod uni_helpers;
+pub mod uni_v2_pair;
 pub mod uni_v2_router;
+pub mod watch;
 
 pub const TOKEN_LIST_ENDPOINT: &str = "https://tokens.coingecko.com/uniswap/all.json";
@@ -4,36 +4,169 @@ use ethers::prelude::*;
 use paris::Logger;
 use token_list::Token;
 
-use crate::{uni_helpers::UniTxnInputs, uni_v2_router::UniV2Router};
+use crate::{
+    amount_format::AmountFormat,
+    config::{FeedConfig, OutputFormat, RevertedMode},
+    execution::SwapExecution,
+    gas::GasCost,
+    provider::TxnDetails,
+    records::{write_jsonl, write_reorg_jsonl},
+    slippage::Slippage,
+    uni_helpers::{DecodedTxn, UniRouterAction},
+};
 
-pub fn log_txns<T>(
-    txns: Vec<&Transaction>,
+pub fn log_txns(
+    decoded: &[DecodedTxn],
+    details: &TxnDetails,
     token_map: &HashMap<String, Token>,
-    uni_router_contract: &UniV2Router<Provider<T>>,
-) where
-    T: JsonRpcClient,
-{
+    feed_config: &FeedConfig,
+) {
+    match feed_config.output {
+        OutputFormat::Text => {}
+        OutputFormat::Jsonl => return write_jsonl(decoded, details, token_map, feed_config),
+        // rows go to the csv sink, stdout keeps the block headers only
+        OutputFormat::Csv => return,
+    }
+
     let mut logger = Logger::new();
-    let call_datas: Vec<(&Transaction, UniTxnInputs)> = txns
-        .iter()
-        .map(|txn| {
-            let call_data = UniTxnInputs::new(txn, uni_router_contract);
-            (*txn, call_data)
-        })
-        .collect();
-    call_datas.iter().for_each(|(txn, call_data)| {
-        logger.indent(1).log(format!(
-            "{} :: {}",
-            log_txn(txn),
-            log_swap_inputs(call_data, token_map)
-        ));
+    let amount_format = &feed_config.amount_format;
+    decoded.iter().for_each(|(txn, call_data)| match call_data {
+        Ok(call_data) => {
+            let reverted = details.reverted(&txn.hash).unwrap_or(false);
+            if reverted && feed_config.reverted == RevertedMode::Hide {
+                return;
+            }
+
+            let mut log_str = log_txn(txn);
+            if reverted {
+                log_str.push_str(&format!(
+                    " :: {}",
+                    log_revert(details.revert_reasons.get(&txn.hash))
+                ));
+            }
+            log_str.push_str(&format!(
+                " :: {}",
+                log_router_action(call_data, token_map, amount_format)
+            ));
+            if let Some(execution) = log_execution(call_data, details.receipts.get(&txn.hash)) {
+                log_str.push_str(&format!(
+                    " :: {}",
+                    execution.log_str(token_map, amount_format)
+                ));
+                if let Some(slippage) =
+                    log_slippage(call_data, &execution, details.quotes.get(&txn.hash))
+                {
+                    log_str.push_str(&format!(" :: {}", slippage.log_str()));
+                }
+            }
+            if feed_config.gas {
+                let gas_cost = GasCost::new(txn, details.receipts.get(&txn.hash), details.base_fee);
+                log_str.push_str(&format!(
+                    " :: {}",
+                    gas_cost.log_str(amount_format, feed_config.eth_usd_price)
+                ));
+            }
+            logger.indent(1).log(log_str);
+        }
+        Err(err) => {
+            // one bad calldata shouldn't take the listener down
+            logger
+                .indent(1)
+                .warn(format!("{} :: Skipped, {}", log_txn(txn), err));
+        }
     })
 }
 
+/// Takes back transactions `log_txns` printed for a block that was
+/// reorganized out of the chain
+pub fn log_reorged_txns(decoded: &[DecodedTxn], details: &TxnDetails, feed_config: &FeedConfig) {
+    match feed_config.output {
+        OutputFormat::Text => {}
+        OutputFormat::Jsonl => return write_reorg_jsonl(decoded, details, feed_config),
+        OutputFormat::Csv => return,
+    }
+
+    let mut logger = Logger::new();
+    for (txn, _) in decoded {
+        let reverted = details.reverted(&txn.hash).unwrap_or(false);
+        if reverted && feed_config.reverted == RevertedMode::Hide {
+            continue;
+        }
+        logger.indent(1).warn(format!(
+            "{} :: REORGED, its block left the chain",
+            log_txn(txn)
+        ));
+    }
+}
+
 fn log_txn(txn: &Transaction) -> String {
     format!("Txn {}", txn.hash)
 }
 
-fn log_swap_inputs(call_data: &UniTxnInputs, token_map: &HashMap<String, Token>) -> String {
-    call_data.log_str(token_map)
+fn log_revert(reason: Option<&String>) -> String {
+    match reason {
+        Some(reason) => format!("REVERTED ({})", reason),
+        None => "REVERTED".to_string(),
+    }
+}
+
+// executed amounts are only known for swaps whose receipt was fetched
+fn log_execution(
+    call_data: &UniRouterAction,
+    receipt: Option<&TransactionReceipt>,
+) -> Option<SwapExecution> {
+    match (call_data, receipt) {
+        (UniRouterAction::Swap(inputs), Some(receipt)) => {
+            SwapExecution::from_receipt(inputs, receipt)
+        }
+        _ => None,
+    }
+}
+
+fn log_slippage(
+    call_data: &UniRouterAction,
+    execution: &SwapExecution,
+    quote: Option<&U256>,
+) -> Option<Slippage> {
+    match (call_data, quote) {
+        (UniRouterAction::Swap(inputs), Some(quote)) => Slippage::new(inputs, execution, *quote),
+        _ => None,
+    }
+}
+
+fn log_router_action(
+    call_data: &UniRouterAction,
+    token_map: &HashMap<String, Token>,
+    amount_format: &AmountFormat,
+) -> String {
+    call_data.log_str(token_map, amount_format)
+}
+
+/// Lists the token list entries whose symbol or address matches `query`,
+/// every entry when it's `None`
+pub fn log_tokens(token_map: &HashMap<String, Token>, query: Option<&str>) {
+    let mut logger = Logger::new();
+    let query = query.map(|query| query.to_lowercase());
+
+    let mut tokens: Vec<&Token> = token_map
+        .values()
+        .filter(|token| match &query {
+            Some(query) => {
+                token.symbol.to_lowercase() == *query || token.address.to_lowercase() == *query
+            }
+            None => true,
+        })
+        .collect();
+    tokens.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+    if tokens.is_empty() {
+        logger.warn("No token matches");
+        return;
+    }
+    for token in tokens {
+        logger.log(format!(
+            "{} :: {} :: {} decimals :: {}",
+            token.symbol, token.address, token.decimals, token.name
+        ));
+    }
 }
@@ -1,107 +1,217 @@
 extern crate core;
 
 use std::collections::HashMap;
-use std::convert::Infallible;
+use std::io::{self, Read};
 use std::sync::Arc;
 
+mod amount_format;
+mod backfill;
+mod checkpoint;
 mod config;
+mod csv_sink;
+mod decode;
+mod execution;
+mod filters;
+mod gas;
 mod logging;
+mod pipeline;
+mod postgres_sink;
 mod provider;
+mod records;
+mod reorg;
+mod sinks;
+mod slippage;
+mod sqlite_sink;
+mod time_machine;
 mod uni_helpers;
+mod uni_v2_pair;
 mod uni_v2_router;
+mod watch;
 
-use anyhow::Ok as AnyhowOk;
+use anyhow::{bail, Ok as AnyhowOk};
 use ethers::prelude::*;
-use ethers::types::Transaction;
 use paris::Logger;
 use token_list::TokenList;
 use uni_listen::TOKEN_LIST_ENDPOINT;
 
 use crate::{
-    config::get_config,
-    logging::log_txns,
-    provider::{get_http_client, get_ws_provider},
-    uni_helpers::{filter_uni_txns, get_uniswap_router_contract},
+    backfill::backfill,
+    config::{get_config, BlockStart, Command},
+    decode::{decode_calldata, decode_txn},
+    filters::Filters,
+    logging::log_tokens,
+    pipeline::FeedContext,
+    provider::get_http_client,
+    reorg::RecentBlocks,
+    sinks::Sinks,
+    time_machine::resolve_start,
+    uni_helpers::get_uniswap_router_contract,
+    watch::{confirmed_block, watch_heads, WatchContext},
 };
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let uni_config = get_config();
-
-    let provider = get_ws_provider(&uni_config.ws_url, 2000).await;
-
-    let mut stream = provider.watch_blocks().await?;
+    let uni_config = get_config()?;
+
+    let token_list = TokenList::from_uri(TOKEN_LIST_ENDPOINT).await;
+
+    // works offline, so it runs before any provider is set up and only uses
+    // the token list when it could be fetched
+    if let Command::DecodeCalldata {
+        calldata,
+        value,
+        amount_format,
+    } = &uni_config.command
+    {
+        let token_map = match token_list {
+            Ok(token_list) => token_list
+                .tokens
+                .into_iter()
+                .map(|token| (token.address.clone(), token))
+                .collect(),
+            Err(_) => {
+                Logger::new().warn("Can't fetch the token list, token amounts stay in base units");
+                HashMap::new()
+            }
+        };
+        let calldata = match calldata {
+            Some(calldata) => calldata.clone(),
+            None => {
+                let mut calldata = String::new();
+                io::stdin().read_to_string(&mut calldata)?;
+                calldata
+            }
+        };
+        return decode_calldata(&calldata, *value, &token_map, amount_format);
+    }
 
-    let client = get_http_client(&uni_config.http_url);
-    let arc_client = Arc::new(client.clone());
+    let client = Arc::new(get_http_client(&uni_config.http_url));
+    let router = get_uniswap_router_contract(client.clone());
 
     let mut logger = Logger::new();
 
-    let uni_router_contract = get_uniswap_router_contract(arc_client.clone());
-
     let mut token_map = HashMap::new();
-    let token_list = TokenList::from_uri(TOKEN_LIST_ENDPOINT)
-        .await
-        .expect("Failed to parse token endpoint");
-
+    let token_list = token_list.expect("Failed to parse token endpoint");
     for token in token_list.tokens {
         token_map.insert(token.address.clone(), token.clone());
     }
 
-    let current_block = client.get_block_number().await.unwrap();
-    let mut starting_block = current_block;
-
-    if uni_config.prev_blocks.is_some() {
-        starting_block = starting_block - uni_config.prev_blocks.unwrap();
-    } else if uni_config.since_block.is_some() {
-        let result: Result<U64, Infallible> = uni_config.since_block.unwrap().try_into();
-        let start_block = match result {
-            Ok(block) => block,
-            Err(_) => panic!("Failed to parse start block correctly"),
-        };
-        starting_block = start_block;
-    }
-
-    while starting_block != current_block {
-        let full_block = client.get_block_with_txs(starting_block).await.unwrap();
-
-        match full_block {
-            Some(block) => {
-                let uniswap_txns: Vec<&Transaction> = filter_uni_txns(&block);
-
-                logger
-                    .done()
-                    .info(format!("Block {}", &block.hash.unwrap()));
-                if !uniswap_txns.is_empty() {
-                    log_txns(uniswap_txns, &token_map, &uni_router_contract)
-                }
-                starting_block = block.number.unwrap() + 1_u64;
+    match &uni_config.command {
+        Command::Watch {
+            catch_up,
+            confirmations,
+            feed,
+            filters,
+            sinks,
+        } => {
+            let filters = Filters::from_config(filters, feed.eth_usd_price, &token_map)?;
+            let resume = catch_up.start == Some(BlockStart::Resume);
+            let mut sinks = Sinks::from_config(feed, sinks, resume).await?;
+            let ctx = WatchContext {
+                feed: FeedContext {
+                    client: &client,
+                    router: &router,
+                    token_map: &token_map,
+                    filters: &filters,
+                    feed_config: feed,
+                },
+                confirmations,
+                concurrency: catch_up.concurrency,
+            };
+
+            // the catch up ends at the last confirmed block, blocks mined
+            // while it ran are backfilled once subscribed
+            let mut recent = RecentBlocks::default();
+            let mut logged_through = None;
+            if let Some(start) = catch_up.start {
+                let current_block = client.get_block_number().await?;
+                let confirmed =
+                    confirmed_block(&client, confirmations.confirmation, current_block).await?;
+                let starting_block =
+                    resolve_start(&client, start, current_block, sinks.checkpoint()?).await?;
+                backfill(
+                    &ctx.feed,
+                    starting_block,
+                    confirmed + 1_u64,
+                    ctx.concurrency,
+                    &mut sinks,
+                    &mut recent,
+                    &mut logger,
+                )
+                .await?;
+                // the range is empty when the start is past the confirmed
+                // block, the feed still carries on from the start then
+                logged_through = Some(if starting_block <= confirmed {
+                    confirmed
+                } else {
+                    starting_block - 1_u64
+                });
             }
-            _ => {}
-        }
-    }
-
-    logger.loading("Waiting for next transaction...");
 
-    if uni_config.watch_blocks {
-        while let Some(block) = stream.next().await {
-            let full_block = client
-                .get_block_with_txs(block)
-                .await?
-                .expect("oh shit, block probably hasnt arrived");
-
-            // filter to uniswap transactions
-            let uniswap_txns: Vec<&Transaction> = filter_uni_txns(&full_block);
-
-            logger
-                .done()
-                .info(format!("New block {}", &full_block.hash.unwrap()));
-            if !uniswap_txns.is_empty() {
-                log_txns(uniswap_txns, &token_map, &uni_router_contract)
+            watch_heads(
+                &uni_config.ws_url,
+                &ctx,
+                &mut sinks,
+                &mut recent,
+                &mut logged_through,
+                &mut logger,
+            )
+            .await?;
+        }
+        Command::Backfill {
+            window,
+            feed,
+            filters,
+            sinks,
+        } => {
+            let filters = Filters::from_config(filters, feed.eth_usd_price, &token_map)?;
+            let start = window.start.expect("backfill config always has a start");
+            let mut sinks = Sinks::from_config(feed, sinks, start == BlockStart::Resume).await?;
+
+            let current_block = client.get_block_number().await?;
+            let starting_block =
+                resolve_start(&client, start, current_block, sinks.checkpoint()?).await?;
+
+            // the window is replayed up to and including its last block
+            let to_block = window.to_block.map_or(current_block, U64::from);
+            if to_block > current_block {
+                bail!(
+                    "--to-block {} is past the chain head {}",
+                    to_block,
+                    current_block
+                );
+            }
+            if starting_block > to_block {
+                bail!(
+                    "Backfill starts at block {}, after --to-block {}",
+                    starting_block,
+                    to_block
+                );
             }
 
-            logger.loading("Waiting for next transaction...");
+            let ctx = FeedContext {
+                client: &client,
+                router: &router,
+                token_map: &token_map,
+                filters: &filters,
+                feed_config: feed,
+            };
+            backfill(
+                &ctx,
+                starting_block,
+                to_block + 1_u64,
+                window.concurrency,
+                &mut sinks,
+                &mut RecentBlocks::default(),
+                &mut logger,
+            )
+            .await?;
+        }
+        Command::Decode { tx_hash, feed } => {
+            decode_txn(&client, &router, *tx_hash, &token_map, feed).await?;
         }
+        Command::Tokens { query } => log_tokens(&token_map, query.as_deref()),
+        Command::DecodeCalldata { .. } => unreachable!("decoded before connecting"),
     }
 
     AnyhowOk(())
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::bail;
+use ethers::prelude::*;
+use token_list::Token;
+
+use crate::{
+    config::{FeedConfig, RevertedMode},
+    filters::Filters,
+    logging::{log_reorged_txns, log_txns},
+    provider::{get_txn_details, TxnDetails},
+    sinks::Sinks,
+    slippage::get_quotes,
+    uni_helpers::{decode_txns, filter_uni_txns},
+    uni_v2_router::UniV2Router,
+};
+
+/// What the backfill and the live watcher fetch and log blocks with
+pub struct FeedContext<'a> {
+    pub client: &'a Provider<Http>,
+    pub router: &'a UniV2Router<Provider<Http>>,
+    pub token_map: &'a HashMap<String, Token>,
+    pub filters: &'a Filters,
+    pub feed_config: &'a FeedConfig,
+}
+
+/// A block with everything its router transactions need to be logged. All the
+/// node round trips happen in `fetch_block_data` so several blocks can be
+/// fetched at once while `log_block_data` still prints them in order.
+pub struct BlockData {
+    pub block: Block<Transaction>,
+    /// router transactions that made it through the filters
+    kept: HashSet<TxHash>,
+    details: TxnDetails,
+}
+
+impl BlockData {
+    // decoding is cheap enough to redo rather than keep borrowing the block
+    fn kept_txns(&self) -> Vec<&Transaction> {
+        filter_uni_txns(&self.block)
+            .into_iter()
+            .filter(|txn| self.kept.contains(&txn.hash))
+            .collect()
+    }
+}
+
+pub async fn fetch_block_data<T>(
+    client: &Provider<Http>,
+    router: &UniV2Router<Provider<Http>>,
+    id: T,
+    filters: &Filters,
+    feed_config: &FeedConfig,
+) -> anyhow::Result<BlockData>
+where
+    T: Into<BlockId> + Send + Sync,
+{
+    let id = id.into();
+    let block = match client.get_block_with_txs(id).await? {
+        Some(block) => block,
+        None => bail!("Block {:?} hasn't arrived", id),
+    };
+
+    // filter to uniswap transactions
+    let uniswap_txns: Vec<&Transaction> = filter_uni_txns(&block);
+    if uniswap_txns.is_empty() {
+        return Ok(BlockData {
+            kept: HashSet::new(),
+            details: TxnDetails::default(),
+            block,
+        });
+    }
+
+    let block_number = block.number.expect("mined blocks have a number");
+    let decoded = filters
+        .apply(router, block_number, decode_txns(&uniswap_txns))
+        .await?;
+
+    let mut details = if feed_config.with_receipts && !decoded.is_empty() {
+        // only fetch what is left after filtering
+        let txns: Vec<&Transaction> = decoded.iter().map(|(txn, _)| *txn).collect();
+        let with_revert_reasons = feed_config.reverted == RevertedMode::Mark;
+        get_txn_details(client, &txns, with_revert_reasons).await
+    } else {
+        TxnDetails::default()
+    };
+    if feed_config.slippage && !decoded.is_empty() {
+        details.quotes = get_quotes(router, &decoded).await;
+    }
+    details.base_fee = block.base_fee_per_gas;
+
+    let kept = decoded.iter().map(|(txn, _)| txn.hash).collect();
+    Ok(BlockData {
+        block,
+        kept,
+        details,
+    })
+}
+
+/// Logs the block's router transactions and writes the block to `sinks`,
+/// even when nothing in it was kept
+pub async fn log_block_data(
+    block_data: &BlockData,
+    token_map: &HashMap<String, Token>,
+    feed_config: &FeedConfig,
+    sinks: &mut Sinks,
+) -> anyhow::Result<()> {
+    let txns = block_data.kept_txns();
+    let decoded = decode_txns(&txns);
+    if !decoded.is_empty() {
+        log_txns(&decoded, &block_data.details, token_map, feed_config);
+    }
+    sinks
+        .write_block(
+            &block_data.block,
+            &decoded,
+            &block_data.details,
+            token_map,
+            feed_config,
+        )
+        .await
+}
+
+/// Prints the block's router transactions without writing them anywhere,
+/// for blocks still waiting to be confirmed
+pub fn log_provisional_block_data(
+    block_data: &BlockData,
+    token_map: &HashMap<String, Token>,
+    feed_config: &FeedConfig,
+) {
+    let txns = block_data.kept_txns();
+    let decoded = decode_txns(&txns);
+    if !decoded.is_empty() {
+        log_txns(&decoded, &block_data.details, token_map, feed_config);
+    }
+}
+
+/// Takes back what `log_block_data` logged and wrote for a block that was
+/// reorganized out of the chain
+pub async fn revert_block_data(
+    block_data: &BlockData,
+    token_map: &HashMap<String, Token>,
+    feed_config: &FeedConfig,
+    sinks: &mut Sinks,
+) -> anyhow::Result<()> {
+    let txns = block_data.kept_txns();
+    let decoded = decode_txns(&txns);
+    if !decoded.is_empty() {
+        log_reorged_txns(&decoded, &block_data.details, feed_config);
+    }
+    sinks
+        .revert_block(
+            &block_data.block,
+            &decoded,
+            &block_data.details,
+            token_map,
+            feed_config,
+        )
+        .await
+}
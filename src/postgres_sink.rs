@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use ethers::prelude::*;
+use paris::Logger;
+use token_list::Token;
+use tokio_postgres::{types::ToSql, Client, NoTls};
+
+use crate::{
+    config::FeedConfig,
+    provider::TxnDetails,
+    records::{ActionRecord, TokenRecord, TxnRecord},
+    uni_helpers::DecodedTxn,
+};
+
+/// Applied in order at startup, each in its own transaction. Only ever
+/// appended to, a database's `schema_migrations` says how far it got.
+const MIGRATIONS: &[&str] = &["
+CREATE TABLE swaps (
+    tx_hash TEXT NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    tx_index BIGINT,
+    sender TEXT NOT NULL,
+    method TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    exact_input BOOLEAN NOT NULL,
+    fee_on_transfer BOOLEAN NOT NULL,
+    hop_index INTEGER NOT NULL,
+    pair TEXT NOT NULL,
+    token_in TEXT NOT NULL,
+    token_out TEXT NOT NULL,
+    amount_in NUMERIC(78, 0) NOT NULL,
+    amount_out NUMERIC(78, 0) NOT NULL,
+    PRIMARY KEY (tx_hash, log_index)
+);
+CREATE INDEX swaps_block_number ON swaps (block_number);
+CREATE INDEX swaps_sender ON swaps (sender);
+CREATE INDEX swaps_token_in ON swaps (token_in);
+CREATE INDEX swaps_token_out ON swaps (token_out);
+"];
+
+const SWAP_COLUMNS: &[&str] = &[
+    "tx_hash",
+    "log_index",
+    "block_number",
+    "block_hash",
+    "tx_index",
+    "sender",
+    "method",
+    "recipient",
+    "exact_input",
+    "fee_on_transfer",
+    "hop_index",
+    "pair",
+    "token_in",
+    "token_out",
+    "amount_in",
+    "amount_out",
+];
+
+/// Upserts one row per executed pair swap into PostgreSQL, keyed on
+/// `(tx_hash, log_index)` so replaying a range rewrites rows instead of
+/// duplicating them. Every block goes out as a single multi-row insert.
+pub struct PostgresSink {
+    client: Client,
+}
+
+impl PostgresSink {
+    /// Connects to `url` and brings the schema up to date
+    pub async fn connect(url: &str) -> anyhow::Result<PostgresSink> {
+        let (mut client, connection) = tokio_postgres::connect(url, NoTls)
+            .await
+            .context("Can't connect to PostgreSQL")?;
+        // the connection does the actual talking to the server until it closes
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                Logger::new().error(format!("PostgreSQL connection closed, {}", err));
+            }
+        });
+
+        migrate(&mut client).await?;
+
+        Ok(PostgresSink { client })
+    }
+
+    pub async fn write_block(
+        &mut self,
+        block: &Block<Transaction>,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> anyhow::Result<()> {
+        let block_number = block.number.expect("mined blocks have a number").as_u64() as i64;
+        let block_hash = format!("{:?}", block.hash.unwrap_or_default());
+
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+        for (txn, call_data) in decoded {
+            let record = TxnRecord::new(txn, call_data, details, token_map, feed_config);
+            let (exact_input, fee_on_transfer, recipient) = match &record.action {
+                Some(ActionRecord::Swap {
+                    exact_input,
+                    fee_on_transfer,
+                    recipient,
+                    ..
+                }) => (*exact_input, *fee_on_transfer, format!("{:?}", recipient)),
+                _ => continue,
+            };
+            let hops = match &record.executed {
+                Some(executed) => &executed.hops,
+                None => continue,
+            };
+
+            for (hop_index, hop) in hops.iter().enumerate() {
+                let log_index = match hop.log_index {
+                    Some(log_index) => log_index as i64,
+                    None => continue,
+                };
+                params.push(Box::new(format!("{:?}", record.tx_hash)));
+                params.push(Box::new(log_index));
+                params.push(Box::new(block_number));
+                params.push(Box::new(block_hash.clone()));
+                params.push(Box::new(
+                    txn.transaction_index.map(|index| index.as_u64() as i64),
+                ));
+                params.push(Box::new(format!("{:?}", record.from)));
+                params.push(Box::new(record.method.clone()));
+                params.push(Box::new(recipient.clone()));
+                params.push(Box::new(exact_input));
+                params.push(Box::new(fee_on_transfer));
+                params.push(Box::new(hop_index as i32));
+                params.push(Box::new(format!("{:?}", hop.pair)));
+                params.push(Box::new(address_str(&hop.amount_in.token)));
+                params.push(Box::new(address_str(&hop.amount_out.token)));
+                params.push(Box::new(hop.amount_in.raw.clone()));
+                params.push(Box::new(hop.amount_out.raw.clone()));
+            }
+        }
+        if params.is_empty() {
+            return Ok(());
+        }
+
+        let params: Vec<&(dyn ToSql + Sync)> = params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+            .collect();
+        let txn = self.client.transaction().await?;
+        txn.execute(
+            upsert_statement(params.len() / SWAP_COLUMNS.len()).as_str(),
+            &params,
+        )
+        .await
+        .with_context(|| format!("Can't write block {} to PostgreSQL", block_number))?;
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Deletes the rows of a block that was reorganized out
+    pub async fn revert_block(&mut self, block: &Block<Transaction>) -> anyhow::Result<()> {
+        let block_hash = format!("{:?}", block.hash.unwrap_or_default());
+        self.client
+            .execute("DELETE FROM swaps WHERE block_hash = $1", &[&block_hash])
+            .await
+            .with_context(|| format!("Can't revert block {} in PostgreSQL", block_hash))?;
+
+        Ok(())
+    }
+}
+
+async fn migrate(client: &mut Client) -> anyhow::Result<()> {
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        )
+        .await?;
+    let applied: i32 = client
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+            &[],
+        )
+        .await?
+        .get(0);
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let version = index as i32 + 1;
+        let txn = client.transaction().await?;
+        txn.batch_execute(migration)
+            .await
+            .with_context(|| format!("Migration {} failed", version))?;
+        txn.execute(
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            &[&version],
+        )
+        .await?;
+        txn.commit().await?;
+    }
+
+    Ok(())
+}
+
+// `INSERT .. VALUES (..), (..) ON CONFLICT DO UPDATE` for `rows` rows of
+// `SWAP_COLUMNS`, amounts are sent as text and cast since `U256` has no
+// numeric mapping
+fn upsert_statement(rows: usize) -> String {
+    let values = (0..rows)
+        .map(|row| {
+            let placeholders = SWAP_COLUMNS
+                .iter()
+                .enumerate()
+                .map(|(column, name)| {
+                    let placeholder = format!("${}", row * SWAP_COLUMNS.len() + column + 1);
+                    match *name {
+                        "amount_in" | "amount_out" => format!("{}::text::numeric", placeholder),
+                        _ => placeholder,
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("({})", placeholders)
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let updates = SWAP_COLUMNS
+        .iter()
+        .filter(|name| !matches!(**name, "tx_hash" | "log_index"))
+        .map(|name| format!("{} = EXCLUDED.{}", name, name))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!(
+        "INSERT INTO swaps ({}) VALUES {} ON CONFLICT (tx_hash, log_index) DO UPDATE SET {}",
+        SWAP_COLUMNS.join(", "),
+        values,
+        updates
+    )
+}
+
+// hops always run between token addresses
+fn address_str(token: &TokenRecord) -> String {
+    token
+        .address
+        .map(|address| format!("{:?}", address))
+        .unwrap_or_default()
+}
@@ -1,7 +1,9 @@
-
-use ethers::prelude::*;
+use std::collections::HashMap;
 use std::time::Duration;
 
+use ethers::{abi, prelude::*, utils::hex};
+use futures::future::join_all;
+
 // pub async fn get_ipc_provider(url: &String, duration: u64) -> Provider<Ipc> {
 //     let provider = Provider::connect_ipc(url)
 //         .await
@@ -10,14 +12,149 @@ use std::time::Duration;
 //     provider
 // }
 
-pub async fn get_ws_provider(url: &String, duration: u64) -> Provider<Ws> {
-    let ws = Ws::connect(url)
-        .await
-        .expect("Can't connect to Websocket Provider");
+pub async fn get_ws_provider(url: &str, duration: u64) -> anyhow::Result<Provider<Ws>> {
+    let ws = Ws::connect(url).await?;
 
-    Provider::new(ws).interval(Duration::from_millis(duration))
+    Ok(Provider::new(ws).interval(Duration::from_millis(duration)))
 }
 
 pub fn get_http_client(url: &String) -> Provider<Http> {
     Provider::<Http>::try_from(url.clone()).expect("Can't connect to HTTP Provider")
 }
+
+// selector of `Error(string)`, what `require` and `revert` encode reasons as
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Node data fetched alongside a block's router transactions
+#[derive(Default)]
+pub struct TxnDetails {
+    pub receipts: HashMap<TxHash, TransactionReceipt>,
+    pub revert_reasons: HashMap<TxHash, String>,
+    /// router quotes at the parent block, see `slippage::get_quotes`
+    pub quotes: HashMap<TxHash, U256>,
+    /// `base_fee_per_gas` of the block, `None` before London
+    pub base_fee: Option<U256>,
+}
+
+impl TxnDetails {
+    /// `None` when the receipt wasn't fetched
+    pub fn reverted(&self, hash: &TxHash) -> Option<bool> {
+        self.receipts.get(hash).map(is_reverted)
+    }
+}
+
+pub fn is_reverted(receipt: &TransactionReceipt) -> bool {
+    receipt.status == Some(U64::zero())
+}
+
+/// Fetches receipts for `txns` and, if asked, the revert reason of the ones
+/// that failed
+pub async fn get_txn_details(
+    client: &Provider<Http>,
+    txns: &[&Transaction],
+    with_revert_reasons: bool,
+) -> TxnDetails {
+    let receipts = get_receipts(client, txns).await;
+
+    let mut revert_reasons = HashMap::new();
+    if with_revert_reasons {
+        let reverted_txns: Vec<&Transaction> = txns
+            .iter()
+            .filter(|txn| receipts.get(&txn.hash).map_or(false, is_reverted))
+            .copied()
+            .collect();
+        let reasons = join_all(
+            reverted_txns
+                .iter()
+                .map(|txn| get_revert_reason(client, txn)),
+        )
+        .await;
+        for (txn, reason) in reverted_txns.iter().zip(reasons) {
+            if let Some(reason) = reason {
+                revert_reasons.insert(txn.hash, reason);
+            }
+        }
+    }
+
+    TxnDetails {
+        receipts,
+        revert_reasons,
+        ..Default::default()
+    }
+}
+
+/// Recovers the revert string of a mined `txn` from a trace of the
+/// transaction itself, trying `debug_traceTransaction` with the call tracer
+/// and then `trace_transaction`. `None` when the node supports neither or the
+/// revert carried no `Error(string)`.
+pub async fn get_revert_reason(client: &Provider<Http>, txn: &Transaction) -> Option<String> {
+    let call_frame: Result<serde_json::Value, _> = client
+        .request(
+            "debug_traceTransaction",
+            (txn.hash, serde_json::json!({ "tracer": "callTracer" })),
+        )
+        .await;
+    if let Ok(call_frame) = call_frame {
+        // newer geth decodes the reason itself
+        if let Some(reason) = call_frame["revertReason"].as_str() {
+            return Some(reason.to_string()).filter(|reason| !reason.is_empty());
+        }
+        return decode_revert_data(call_frame["output"].as_str()?);
+    }
+
+    let traces: Vec<serde_json::Value> =
+        client.request("trace_transaction", [txn.hash]).await.ok()?;
+    let top_call = traces.iter().find(|trace| {
+        trace["traceAddress"]
+            .as_array()
+            .map_or(false, Vec::is_empty)
+    })?;
+    decode_revert_data(top_call["result"]["output"].as_str()?)
+}
+
+// revert data is `Error(string)` ABI encoded, e.g. for
+// `require(deadline >= block.timestamp, "UniswapV2Router: EXPIRED")`
+fn decode_revert_data(output: &str) -> Option<String> {
+    let data = hex::decode(output.trim_start_matches("0x")).ok()?;
+    if data.len() < 4 || data[..4] != ERROR_SELECTOR {
+        return None;
+    }
+    match abi::decode(&[abi::ParamType::String], &data[4..])
+        .ok()?
+        .pop()?
+    {
+        abi::Token::String(reason) if !reason.is_empty() => Some(reason),
+        _ => None,
+    }
+}
+
+/// Fetches the receipts of `txns` concurrently, keyed by transaction hash.
+/// Receipts the node fails to return are left out.
+pub async fn get_receipts(
+    client: &Provider<Http>,
+    txns: &[&Transaction],
+) -> HashMap<TxHash, TransactionReceipt> {
+    let receipts = join_all(
+        txns.iter()
+            .map(|txn| client.get_transaction_receipt(txn.hash)),
+    )
+    .await;
+
+    receipts
+        .into_iter()
+        .filter_map(|receipt| receipt.ok().flatten())
+        .map(|receipt| (receipt.transaction_hash, receipt))
+        .collect()
+}
+
+/// The newest block the beacon chain has finalized. `BlockNumber` has no
+/// `finalized` tag yet, so the tag goes through the raw RPC.
+pub async fn get_finalized_block_number(client: &Provider<Http>) -> anyhow::Result<U64> {
+    let block: Option<Block<H256>> = client
+        .request("eth_getBlockByNumber", ("finalized", false))
+        .await?;
+    match block.and_then(|block| block.number) {
+        Some(number) => Ok(number),
+        None => anyhow::bail!("The node doesn't know a finalized block"),
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use ethers::prelude::*;
+use serde::Serialize;
+use token_list::Token;
+
+use crate::{
+    amount_format::{format_amount, AmountFormat},
+    config::{FeedConfig, RevertedMode},
+    execution::SwapExecution,
+    gas::{GasCost, TxnType},
+    provider::TxnDetails,
+    slippage::Slippage,
+    uni_helpers::{
+        address_key, router_method_name, DecodeError, DecodedTxn, LiquidityDirection,
+        UniLiquidityInputs, UniRouterAction, UniTxnInputs, LP_TOKEN_DECIMALS,
+    },
+};
+
+const ETH_DECIMALS: usize = 18;
+
+/// One line of `--output jsonl`, one per router transaction. The schema is
+/// documented in the README, fields are only ever added to it.
+#[derive(Serialize)]
+pub struct TxnRecord {
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub tx_hash: TxHash,
+    pub from: Address,
+    pub method: String,
+    /// `None` without a receipt
+    pub status: Option<TxnStatus>,
+    pub revert_reason: Option<String>,
+    /// `None` when the calldata didn't decode, see `error`
+    pub action: Option<ActionRecord>,
+    pub error: Option<String>,
+    pub executed: Option<ExecutionRecord>,
+    pub slippage: Option<Slippage>,
+    pub gas: Option<GasRecord>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TxnStatus {
+    Success,
+    Reverted,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ActionRecord {
+    Swap {
+        /// `true` bounds `destination` from below, `false` bounds `origin` from above
+        exact_input: bool,
+        fee_on_transfer: bool,
+        recipient: Address,
+        path: Vec<TokenRecord>,
+        origin: AmountRecord,
+        destination: AmountRecord,
+    },
+    Liquidity {
+        direction: LiquidityDirection,
+        permit: bool,
+        fee_on_transfer: bool,
+        recipient: Address,
+        amount_a_desired: Option<AmountRecord>,
+        amount_b_desired: Option<AmountRecord>,
+        amount_a_min: AmountRecord,
+        amount_b_min: AmountRecord,
+        /// LP tokens burnt, removals only
+        liquidity: Option<AmountRecord>,
+    },
+}
+
+/// A token as the token list knows it. ETH has no address.
+#[derive(Serialize)]
+pub struct TokenRecord {
+    pub address: Option<Address>,
+    pub symbol: Option<String>,
+    pub decimals: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct AmountRecord {
+    #[serde(flatten)]
+    pub token: TokenRecord,
+    /// base units as a decimal string, exact
+    pub raw: String,
+    /// `raw` shifted by `decimals`, exact. `None` when the decimals are unknown.
+    pub formatted: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HopRecord {
+    pub pair: Address,
+    /// position of the pair's `Swap` event in the block
+    pub log_index: Option<u64>,
+    pub amount_in: AmountRecord,
+    pub amount_out: AmountRecord,
+}
+
+#[derive(Serialize)]
+pub struct ExecutionRecord {
+    pub hops: Vec<HopRecord>,
+    /// what reached the recipient, `None` for ETH payouts
+    pub received: Option<AmountRecord>,
+}
+
+#[derive(Serialize)]
+pub struct GasRecord {
+    pub txn_type: TxnType,
+    pub gas_limit: String,
+    pub gas_used: Option<String>,
+    pub cost: Option<AmountRecord>,
+    pub cost_usd: Option<f64>,
+    /// wei per gas on top of the base fee
+    pub priority_tip: Option<String>,
+}
+
+/// Printed for a transaction whose block was reorganized out of the chain,
+/// after its `TxnRecord`. The transaction is printed again if it lands in the
+/// canonical chain.
+#[derive(Serialize)]
+pub struct ReorgRecord {
+    /// always `"reorged"`, `TxnRecord`s have no `event`
+    pub event: &'static str,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<H256>,
+    pub tx_hash: TxHash,
+}
+
+impl TxnRecord {
+    pub fn new(
+        txn: &Transaction,
+        call_data: &Result<UniRouterAction, DecodeError>,
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> TxnRecord {
+        let receipt = details.receipts.get(&txn.hash);
+        let status = details.reverted(&txn.hash).map(|reverted| {
+            if reverted {
+                TxnStatus::Reverted
+            } else {
+                TxnStatus::Success
+            }
+        });
+
+        let (action, error) = match call_data {
+            Ok(call_data) => (Some(ActionRecord::new(call_data, token_map)), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        let swap_execution = match (call_data, receipt) {
+            (Ok(UniRouterAction::Swap(inputs)), Some(receipt)) => {
+                SwapExecution::from_receipt(inputs, receipt).map(|execution| (inputs, execution))
+            }
+            _ => None,
+        };
+        let slippage = swap_execution.as_ref().and_then(|(inputs, execution)| {
+            details
+                .quotes
+                .get(&txn.hash)
+                .and_then(|quote| Slippage::new(inputs, execution, *quote))
+        });
+        let executed = swap_execution
+            .as_ref()
+            .map(|(_, execution)| ExecutionRecord::new(execution, token_map));
+
+        let gas = if feed_config.gas {
+            Some(GasRecord::new(
+                txn,
+                receipt,
+                details.base_fee,
+                feed_config.eth_usd_price,
+            ))
+        } else {
+            None
+        };
+
+        TxnRecord {
+            block_number: txn.block_number.map(|number| number.as_u64()),
+            block_hash: txn.block_hash,
+            tx_hash: txn.hash,
+            from: txn.from,
+            method: router_method_name(&txn.input),
+            status,
+            revert_reason: details.revert_reasons.get(&txn.hash).cloned(),
+            action,
+            error,
+            executed,
+            slippage,
+            gas,
+        }
+    }
+}
+
+impl ActionRecord {
+    pub fn new(call_data: &UniRouterAction, token_map: &HashMap<String, Token>) -> ActionRecord {
+        match call_data {
+            UniRouterAction::Swap(inputs) => swap_record(inputs, token_map),
+            UniRouterAction::Liquidity(inputs) => liquidity_record(inputs, token_map),
+        }
+    }
+}
+
+fn swap_record(inputs: &UniTxnInputs, token_map: &HashMap<String, Token>) -> ActionRecord {
+    ActionRecord::Swap {
+        exact_input: inputs.exact_input(),
+        fee_on_transfer: inputs.fee_on_transfer(),
+        recipient: inputs.recipient(),
+        path: inputs
+            .path()
+            .iter()
+            .map(|address| TokenRecord::new(Some(*address), token_map))
+            .collect(),
+        origin: AmountRecord::new(inputs.origin_amount(), inputs.origin_address(), token_map),
+        destination: AmountRecord::new(
+            inputs.destination_amount(),
+            inputs.destination_address(),
+            token_map,
+        ),
+    }
+}
+
+fn liquidity_record(
+    inputs: &UniLiquidityInputs,
+    token_map: &HashMap<String, Token>,
+) -> ActionRecord {
+    let amount_a = |amount: U256| AmountRecord::new(amount, inputs.token_a(), token_map);
+    let amount_b = |amount: U256| AmountRecord::new(amount, inputs.token_b(), token_map);
+
+    ActionRecord::Liquidity {
+        direction: inputs.direction(),
+        permit: inputs.permit(),
+        fee_on_transfer: inputs.fee_on_transfer(),
+        recipient: inputs.recipient(),
+        amount_a_desired: inputs.amount_a_desired().map(amount_a),
+        amount_b_desired: inputs.amount_b_desired().map(amount_b),
+        amount_a_min: amount_a(inputs.amount_a_min()),
+        amount_b_min: amount_b(inputs.amount_b_min()),
+        liquidity: inputs.liquidity().map(|liquidity| AmountRecord {
+            token: TokenRecord {
+                address: None,
+                symbol: Some("UNI-V2".to_string()),
+                decimals: Some(LP_TOKEN_DECIMALS),
+            },
+            raw: liquidity.to_string(),
+            formatted: Some(format_exact(&liquidity, LP_TOKEN_DECIMALS)),
+        }),
+    }
+}
+
+impl TokenRecord {
+    /// `None` is ETH
+    pub fn new(address: Option<Address>, token_map: &HashMap<String, Token>) -> TokenRecord {
+        match address {
+            Some(address) => {
+                let token = token_map.get(&address_key(&address));
+                TokenRecord {
+                    address: Some(address),
+                    symbol: token.map(|t| t.symbol.clone()),
+                    decimals: token.map(|t| t.decimals as usize),
+                }
+            }
+            None => TokenRecord {
+                address: None,
+                symbol: Some("ETH".to_string()),
+                decimals: Some(ETH_DECIMALS),
+            },
+        }
+    }
+}
+
+impl AmountRecord {
+    pub fn new(
+        amount: U256,
+        address: Option<Address>,
+        token_map: &HashMap<String, Token>,
+    ) -> AmountRecord {
+        let token = TokenRecord::new(address, token_map);
+        let formatted = token
+            .decimals
+            .map(|decimals| format_exact(&amount, decimals));
+        AmountRecord {
+            token,
+            raw: amount.to_string(),
+            formatted,
+        }
+    }
+}
+
+impl ExecutionRecord {
+    pub fn new(execution: &SwapExecution, token_map: &HashMap<String, Token>) -> ExecutionRecord {
+        let hops = execution
+            .hops
+            .iter()
+            .map(|hop| HopRecord {
+                pair: hop.pair,
+                log_index: hop.log_index,
+                amount_in: AmountRecord::new(hop.amount_in, Some(hop.token_in), token_map),
+                amount_out: AmountRecord::new(hop.amount_out, Some(hop.token_out), token_map),
+            })
+            .collect();
+        let last_token = execution.hops.last().map(|hop| hop.token_out);
+        let received = execution
+            .received
+            .map(|received| AmountRecord::new(received, last_token, token_map));
+
+        ExecutionRecord { hops, received }
+    }
+}
+
+impl GasRecord {
+    pub fn new(
+        txn: &Transaction,
+        receipt: Option<&TransactionReceipt>,
+        base_fee: Option<U256>,
+        eth_usd_price: Option<f64>,
+    ) -> GasRecord {
+        let gas_cost = GasCost::new(txn, receipt, base_fee);
+        GasRecord {
+            txn_type: gas_cost.txn_type,
+            gas_limit: txn.gas.to_string(),
+            gas_used: receipt
+                .and_then(|receipt| receipt.gas_used)
+                .map(|gas_used| gas_used.to_string()),
+            cost: gas_cost
+                .cost
+                .map(|cost| AmountRecord::new(cost, None, &HashMap::new())),
+            cost_usd: gas_cost.cost_usd(eth_usd_price),
+            priority_tip: gas_cost.priority_tip.map(|tip| tip.to_string()),
+        }
+    }
+}
+
+/// Prints one JSON object per line to stdout, hidden reverts left out
+pub fn write_jsonl(
+    decoded: &[DecodedTxn],
+    details: &TxnDetails,
+    token_map: &HashMap<String, Token>,
+    feed_config: &FeedConfig,
+) {
+    for (txn, call_data) in decoded {
+        let record = TxnRecord::new(txn, call_data, details, token_map, feed_config);
+        if record.status == Some(TxnStatus::Reverted) && feed_config.reverted == RevertedMode::Hide
+        {
+            continue;
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&record).expect("records always serialize")
+        );
+    }
+}
+
+/// The `write_jsonl` counterpart for transactions of a reorganized block
+pub fn write_reorg_jsonl(decoded: &[DecodedTxn], details: &TxnDetails, feed_config: &FeedConfig) {
+    for (txn, _) in decoded {
+        let reverted = details.reverted(&txn.hash).unwrap_or(false);
+        if reverted && feed_config.reverted == RevertedMode::Hide {
+            continue;
+        }
+        let record = ReorgRecord {
+            event: "reorged",
+            block_number: txn.block_number.map(|number| number.as_u64()),
+            block_hash: txn.block_hash,
+            tx_hash: txn.hash,
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&record).expect("records always serialize")
+        );
+    }
+}
+
+// every digit, no grouping, so consumers can parse it back
+fn format_exact(amount: &U256, decimals: usize) -> String {
+    let exact = AmountFormat {
+        significant_digits: None,
+        thousands_separator: false,
+        compact: false,
+    };
+    format_amount(amount, decimals, &exact)
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+
+use anyhow::bail;
+use ethers::prelude::*;
+
+use crate::pipeline::BlockData;
+
+/// How many of the latest logged blocks are kept to be taken back on a reorg
+pub const REORG_DEPTH: usize = 64;
+
+/// The latest blocks of the chain, oldest first and numbered consecutively.
+/// Blocks waiting for confirmations are kept along with the `REORG_DEPTH`
+/// latest ones already logged.
+#[derive(Default)]
+pub struct RecentBlocks {
+    blocks: VecDeque<BlockData>,
+}
+
+/// How a new head connects to `RecentBlocks`
+pub struct Fork {
+    /// the newest block both chains share, everything kept above it is orphaned
+    pub ancestor: U64,
+    /// canonical blocks between `ancestor` and the new head, oldest first
+    pub missing: Vec<H256>,
+    /// the chains didn't meet within the kept blocks, so orphaned blocks
+    /// older than those can't be taken back
+    pub too_deep: bool,
+}
+
+impl RecentBlocks {
+    pub fn push(&mut self, block_data: BlockData) {
+        self.blocks.push_back(block_data);
+    }
+
+    /// Drops logged blocks beyond `REORG_DEPTH`, `logged_through` being the
+    /// last block the sinks have
+    pub fn prune(&mut self, logged_through: U64) {
+        let logged = self
+            .blocks
+            .iter()
+            .take_while(|block_data| block_number(block_data) <= logged_through)
+            .count();
+        for _ in REORG_DEPTH..logged {
+            self.blocks.pop_front();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockData> {
+        self.blocks.iter()
+    }
+
+    pub fn contains(&self, hash: H256) -> bool {
+        self.blocks
+            .iter()
+            .any(|block_data| block_data.block.hash == Some(hash))
+    }
+
+    /// Removes the blocks above `number`, newest first
+    pub fn split_off_after(&mut self, number: U64) -> Vec<BlockData> {
+        let mut orphaned = Vec::new();
+        while let Some(block_data) = self.blocks.back() {
+            if block_number(block_data) <= number {
+                break;
+            }
+            orphaned.extend(self.blocks.pop_back());
+        }
+        orphaned
+    }
+
+    fn hash_at(&self, number: U64) -> Option<H256> {
+        let oldest = self.oldest()?;
+        if number < oldest {
+            return None;
+        }
+        self.blocks
+            .get((number - oldest).as_usize())
+            .and_then(|block_data| block_data.block.hash)
+    }
+
+    fn oldest(&self) -> Option<U64> {
+        self.blocks.front().map(block_number)
+    }
+
+    fn newest(&self) -> Option<U64> {
+        self.blocks.back().map(block_number)
+    }
+}
+
+/// Finds where `head` joins the kept blocks by walking its parents back
+/// until one of them is a kept block. `None` when `head` is already kept,
+/// or is a stale sibling of a kept block rather than the chain's new head.
+/// Blocks the stream skipped come back in `missing` along with the
+/// replacements of orphaned ones. With nothing kept yet, `missing` holds every
+/// block after `logged_through`, the last block the sinks have.
+pub async fn find_fork<T>(
+    client: &Provider<Http>,
+    recent: &RecentBlocks,
+    logged_through: Option<U64>,
+    head: &Block<T>,
+) -> anyhow::Result<Option<Fork>> {
+    let number = head.number.expect("mined blocks have a number");
+    let hash = head.hash.expect("mined blocks have a hash");
+    let (oldest, newest) = match (recent.oldest(), recent.newest()) {
+        (Some(oldest), Some(newest)) => (oldest, newest),
+        _ => {
+            let ancestor = match logged_through {
+                Some(logged) if logged < number => logged,
+                _ => number.saturating_sub(U64::one()),
+            };
+            let mut missing = Vec::new();
+            let (mut parent_number, mut parent_hash) =
+                (number.saturating_sub(U64::one()), head.parent_hash);
+            while parent_number > ancestor {
+                missing.push(parent_hash);
+                parent_number = parent_number - 1_u64;
+                if parent_number > ancestor {
+                    parent_hash = get_parent_hash(client, parent_hash).await?;
+                }
+            }
+            missing.reverse();
+            return Ok(Some(Fork {
+                ancestor,
+                missing,
+                too_deep: false,
+            }));
+        }
+    };
+
+    if recent.contains(hash) {
+        return Ok(None);
+    }
+    if number <= newest {
+        let canonical = client.get_block(number).await?.and_then(|block| block.hash);
+        if canonical != Some(hash) {
+            return Ok(None);
+        }
+    }
+
+    let mut missing = Vec::new();
+    let (mut parent_number, mut parent_hash) = (number - 1_u64, head.parent_hash);
+    loop {
+        if parent_number < oldest {
+            missing.reverse();
+            return Ok(Some(Fork {
+                ancestor: parent_number,
+                missing,
+                too_deep: true,
+            }));
+        }
+        if recent.hash_at(parent_number) == Some(parent_hash) {
+            missing.reverse();
+            return Ok(Some(Fork {
+                ancestor: parent_number,
+                missing,
+                too_deep: false,
+            }));
+        }
+
+        missing.push(parent_hash);
+        parent_number = parent_number - 1_u64;
+        parent_hash = get_parent_hash(client, parent_hash).await?;
+    }
+}
+
+async fn get_parent_hash(client: &Provider<Http>, hash: H256) -> anyhow::Result<H256> {
+    match client.get_block(hash).await? {
+        Some(block) => Ok(block.parent_hash),
+        None => bail!("Block {} hasn't arrived", hash),
+    }
+}
+
+pub fn block_number(block_data: &BlockData) -> U64 {
+    block_data.block.number.expect("mined blocks have a number")
+}
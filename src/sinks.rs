@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use ethers::prelude::*;
+use token_list::Token;
+
+use crate::{
+    checkpoint::{Checkpoint, CheckpointFile},
+    config::{FeedConfig, OutputFormat, SinkConfig},
+    csv_sink::CsvSink,
+    postgres_sink::PostgresSink,
+    provider::TxnDetails,
+    sqlite_sink::SqliteSink,
+    uni_helpers::DecodedTxn,
+};
+
+/// Everything decoded blocks are written to besides stdout
+#[derive(Default)]
+pub struct Sinks {
+    pub csv: Option<CsvSink>,
+    pub sqlite: Option<SqliteSink>,
+    pub postgres: Option<PostgresSink>,
+    /// saved once every other sink has the block
+    pub checkpoint: Option<CheckpointFile>,
+}
+
+impl Sinks {
+    /// `resume` keeps what a previous run wrote to `--out-file`
+    pub async fn from_config(
+        feed_config: &FeedConfig,
+        sink_config: &SinkConfig,
+        resume: bool,
+    ) -> anyhow::Result<Sinks> {
+        let csv = match (&feed_config.output, &sink_config.out_file) {
+            (OutputFormat::Csv, Some(out_file)) if resume => Some(CsvSink::append(out_file)?),
+            (OutputFormat::Csv, Some(out_file)) => Some(CsvSink::create(out_file)?),
+            _ => None,
+        };
+        let sqlite = match &sink_config.sqlite {
+            Some(path) => Some(SqliteSink::open(path)?),
+            None => None,
+        };
+        let postgres = match &sink_config.postgres {
+            Some(url) => Some(PostgresSink::connect(url).await?),
+            None => None,
+        };
+
+        let checkpoint = sink_config.checkpoint_file.clone().map(CheckpointFile::new);
+
+        Ok(Sinks {
+            csv,
+            sqlite,
+            postgres,
+            checkpoint,
+        })
+    }
+
+    /// The last block fully written, from `--checkpoint-file` or else the
+    /// SQLite progress table
+    pub fn checkpoint(&self) -> anyhow::Result<Option<Checkpoint>> {
+        if let Some(checkpoint) = &self.checkpoint {
+            return checkpoint.load();
+        }
+        if let Some(sqlite) = &self.sqlite {
+            return sqlite.checkpoint();
+        }
+
+        Ok(None)
+    }
+
+    /// Called for every block, `decoded` is empty when none of its router
+    /// transactions made it through the filters
+    pub async fn write_block(
+        &mut self,
+        block: &Block<Transaction>,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> anyhow::Result<()> {
+        if let Some(csv) = &mut self.csv {
+            if !decoded.is_empty() {
+                csv.write_txns(decoded, details, token_map, feed_config)?;
+            }
+        }
+        if let Some(sqlite) = &mut self.sqlite {
+            sqlite.write_block(block, decoded, details, token_map, feed_config)?;
+        }
+        if let Some(postgres) = &mut self.postgres {
+            postgres
+                .write_block(block, decoded, details, token_map, feed_config)
+                .await?;
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.save(&Checkpoint::from_block(block))?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes back a block `write_block` wrote that was reorganized out of the
+    /// chain. Orphaned blocks are reverted newest first, so the checkpoint
+    /// ends up on the last block both chains share.
+    pub async fn revert_block(
+        &mut self,
+        block: &Block<Transaction>,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> anyhow::Result<()> {
+        if let Some(csv) = &mut self.csv {
+            if !decoded.is_empty() {
+                csv.revert_txns(decoded, details, token_map, feed_config)?;
+            }
+        }
+        if let Some(sqlite) = &mut self.sqlite {
+            sqlite.revert_block(block)?;
+        }
+        if let Some(postgres) = &mut self.postgres {
+            postgres.revert_block(block).await?;
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.save(&Checkpoint::parent_of(block))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use ethers::prelude::*;
+use futures::future::join_all;
+use serde::Serialize;
+
+use crate::{
+    execution::SwapExecution,
+    uni_helpers::{DecodedTxn, UniRouterAction, UniTxnInputs},
+    uni_v2_router::UniV2Router,
+};
+
+const BPS: u64 = 10_000;
+
+/// Slippage tolerance a trader set through `amountOutMin`/`amountInMax`, measured
+/// against the router quote at the parent block, and how much of it the
+/// executed amounts used up
+#[derive(Serialize)]
+pub struct Slippage {
+    /// how far the bound sits from the quote, in basis points of the quote
+    pub tolerance_bps: u64,
+    /// share of the tolerance consumed, in percent. Negative when the swap
+    /// executed better than quoted, above 100 can't happen for a swap that
+    /// didn't revert. `None` when the bound leaves no tolerance at all.
+    pub consumed_pct: Option<i64>,
+}
+
+impl Slippage {
+    /// `quote` is `getAmountsOut` for exact input swaps and `getAmountsIn` for
+    /// exact output swaps, taken at the end of the swap path facing the bound
+    pub fn new(inputs: &UniTxnInputs, execution: &SwapExecution, quote: U256) -> Option<Slippage> {
+        if quote.is_zero() {
+            return None;
+        }
+
+        // exact input swaps lose by receiving less, exact output swaps by paying more
+        let (executed, tolerance, worse_than_quote) = if inputs.exact_input() {
+            let executed = execution.amount_out();
+            (
+                executed,
+                quote.saturating_sub(inputs.destination_amount()),
+                executed < quote,
+            )
+        } else {
+            let executed = execution.amount_in();
+            (
+                executed,
+                inputs.origin_amount().saturating_sub(quote),
+                executed > quote,
+            )
+        };
+
+        let consumed_pct = if tolerance.is_zero() {
+            None
+        } else {
+            let deviation = if executed > quote {
+                executed - quote
+            } else {
+                quote - executed
+            };
+            let pct = ratio(deviation, tolerance, 100) as i64;
+            Some(if worse_than_quote { pct } else { -pct })
+        };
+
+        Some(Slippage {
+            tolerance_bps: ratio(tolerance, quote, BPS),
+            consumed_pct,
+        })
+    }
+
+    pub fn log_str(&self) -> String {
+        let tolerance = format!(
+            "{}.{:02}%",
+            self.tolerance_bps / 100,
+            self.tolerance_bps % 100
+        );
+        match self.consumed_pct {
+            Some(consumed) => format!("Slippage {} allowed, {}% used", tolerance, consumed),
+            None => format!("Slippage {} allowed", tolerance),
+        }
+    }
+}
+
+// `numerator * scale / denominator`, saturating at `i64::MAX` so the result
+// fits the signed consumed share
+fn ratio(numerator: U256, denominator: U256, scale: u64) -> u64 {
+    let value = numerator.saturating_mul(U256::from(scale)) / denominator;
+    value.min(U256::from(i64::MAX as u64)).as_u64()
+}
+
+/// Quotes every decoded swap against the pair reserves of the block before it
+/// was mined, which is the closest thing to the price the trader saw when
+/// picking the bound. Swaps whose quote call fails are left out.
+pub async fn get_quotes<M: Middleware>(
+    router: &UniV2Router<M>,
+    decoded: &[DecodedTxn<'_>],
+) -> HashMap<TxHash, U256> {
+    let swaps: Vec<(TxHash, U64, &UniTxnInputs)> = decoded
+        .iter()
+        .filter_map(|(txn, call_data)| match (call_data, txn.block_number) {
+            (Ok(UniRouterAction::Swap(inputs)), Some(block_number)) => {
+                Some((txn.hash, block_number - 1_u64, inputs))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let quotes = join_all(
+        swaps
+            .iter()
+            .map(|(_, parent_block, inputs)| get_quote(router, *parent_block, inputs)),
+    )
+    .await;
+
+    swaps
+        .iter()
+        .zip(quotes)
+        .filter_map(|((hash, _, _), quote)| quote.map(|quote| (*hash, quote)))
+        .collect()
+}
+
+async fn get_quote<M: Middleware>(
+    router: &UniV2Router<M>,
+    parent_block: U64,
+    inputs: &UniTxnInputs,
+) -> Option<U256> {
+    let path = inputs.path().to_vec();
+    if inputs.exact_input() {
+        let amounts = router
+            .get_amounts_out(inputs.origin_amount(), path)
+            .block(parent_block)
+            .call()
+            .await
+            .ok()?;
+        amounts.last().copied()
+    } else {
+        let amounts = router
+            .get_amounts_in(inputs.destination_amount(), path)
+            .block(parent_block)
+            .call()
+            .await
+            .ok()?;
+        amounts.first().copied()
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use ethers::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension, Transaction as SqlTransaction};
+use token_list::Token;
+
+use crate::{
+    checkpoint::Checkpoint,
+    config::FeedConfig,
+    provider::TxnDetails,
+    records::{ActionRecord, TokenRecord, TxnRecord, TxnStatus},
+    uni_helpers::DecodedTxn,
+};
+
+// `IF NOT EXISTS` throughout so an existing database is appended to
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    number INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    base_fee TEXT
+);
+CREATE TABLE IF NOT EXISTS txns (
+    hash TEXT PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    tx_index INTEGER,
+    sender TEXT NOT NULL,
+    method TEXT NOT NULL,
+    status TEXT,
+    revert_reason TEXT,
+    error TEXT,
+    gas_used TEXT,
+    gas_cost TEXT,
+    record TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS txns_block_number ON txns (block_number);
+CREATE INDEX IF NOT EXISTS txns_sender ON txns (sender);
+CREATE TABLE IF NOT EXISTS swaps (
+    tx_hash TEXT PRIMARY KEY,
+    exact_input INTEGER NOT NULL,
+    fee_on_transfer INTEGER NOT NULL,
+    recipient TEXT NOT NULL,
+    origin_token TEXT,
+    origin_amount TEXT NOT NULL,
+    destination_token TEXT,
+    destination_amount TEXT NOT NULL,
+    executed_in TEXT,
+    executed_out TEXT
+);
+CREATE INDEX IF NOT EXISTS swaps_origin_token ON swaps (origin_token);
+CREATE INDEX IF NOT EXISTS swaps_destination_token ON swaps (destination_token);
+CREATE TABLE IF NOT EXISTS swap_hops (
+    tx_hash TEXT NOT NULL,
+    hop_index INTEGER NOT NULL,
+    token_in TEXT NOT NULL,
+    token_out TEXT NOT NULL,
+    amount_in TEXT NOT NULL,
+    amount_out TEXT NOT NULL,
+    PRIMARY KEY (tx_hash, hop_index)
+);
+CREATE INDEX IF NOT EXISTS swap_hops_token_in ON swap_hops (token_in);
+CREATE INDEX IF NOT EXISTS swap_hops_token_out ON swap_hops (token_out);
+CREATE TABLE IF NOT EXISTS progress (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    last_block INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+";
+
+// `PRAGMA user_version` counts how many of these a database has had
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE progress ADD COLUMN last_hash TEXT;",
+    // every path hop gets a row, executed amounts only come with a receipt
+    "CREATE TABLE swap_hops_new (
+        tx_hash TEXT NOT NULL,
+        hop_index INTEGER NOT NULL,
+        token_in TEXT NOT NULL,
+        token_out TEXT NOT NULL,
+        amount_in TEXT,
+        amount_out TEXT,
+        PRIMARY KEY (tx_hash, hop_index)
+    );
+    INSERT INTO swap_hops_new SELECT * FROM swap_hops;
+    DROP TABLE swap_hops;
+    ALTER TABLE swap_hops_new RENAME TO swap_hops;
+    CREATE INDEX swap_hops_token_in ON swap_hops (token_in);
+    CREATE INDEX swap_hops_token_out ON swap_hops (token_out);",
+];
+
+/// Stores every block the feed goes through, with its router transactions
+/// and their decoded swaps, in a SQLite database given by `--sqlite`.
+/// Addresses and hashes are lowercase 0x strings, amounts are base units as
+/// decimal strings so nothing is rounded. Rows are replaced when a block is
+/// written again, so overlapping backfills are harmless.
+pub struct SqliteSink {
+    conn: Connection,
+}
+
+impl SqliteSink {
+    /// Opens `path`, creating the database and its tables when missing
+    pub fn open(path: &Path) -> anyhow::Result<SqliteSink> {
+        let conn =
+            Connection::open(path).with_context(|| format!("Can't open {}", path.display()))?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| format!("Can't create the tables in {}", path.display()))?;
+
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                index + 1
+            ))
+            .with_context(|| format!("Can't migrate {}", path.display()))?;
+        }
+
+        Ok(SqliteSink { conn })
+    }
+
+    /// The last block written, `None` for a new database
+    pub fn checkpoint(&self) -> anyhow::Result<Option<Checkpoint>> {
+        let progress: Option<(u64, Option<String>)> = self
+            .conn
+            .query_row(
+                "SELECT last_block, last_hash FROM progress WHERE id = 0",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        Ok(match progress {
+            Some((number, Some(hash))) => Some(Checkpoint {
+                number,
+                hash: hash.parse()?,
+            }),
+            // written before hashes were tracked
+            Some((number, None)) => Some(Checkpoint {
+                number,
+                hash: H256::zero(),
+            }),
+            None => None,
+        })
+    }
+
+    /// Writes the block, its transactions and the progress marker in one
+    /// SQL transaction, so a block is either fully stored or not at all
+    pub fn write_block(
+        &mut self,
+        block: &Block<Transaction>,
+        decoded: &[DecodedTxn],
+        details: &TxnDetails,
+        token_map: &HashMap<String, Token>,
+        feed_config: &FeedConfig,
+    ) -> anyhow::Result<()> {
+        let block_number = block.number.expect("mined blocks have a number").as_u64();
+        let db_txn = self.conn.transaction()?;
+
+        db_txn.execute(
+            "INSERT OR REPLACE INTO blocks (number, hash, parent_hash, timestamp, base_fee)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                block_number,
+                format!("{:?}", block.hash.unwrap_or_default()),
+                format!("{:?}", block.parent_hash),
+                block.timestamp.as_u64(),
+                block.base_fee_per_gas.map(|fee| fee.to_string()),
+            ],
+        )?;
+
+        for (txn, call_data) in decoded {
+            let record = TxnRecord::new(txn, call_data, details, token_map, feed_config);
+            insert_txn(&db_txn, txn, &record, details)?;
+            if let Some(ActionRecord::Swap { .. }) = &record.action {
+                insert_swap(&db_txn, &record)?;
+            }
+        }
+
+        db_txn.execute(
+            "INSERT OR REPLACE INTO progress (id, last_block, last_hash, updated_at)
+             VALUES (0, ?1, ?2, ?3)",
+            params![
+                block_number,
+                format!("{:?}", block.hash.unwrap_or_default()),
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        db_txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Deletes a block that was reorganized out with everything stored for
+    /// it, and moves the progress marker back to its parent
+    pub fn revert_block(&mut self, block: &Block<Transaction>) -> anyhow::Result<()> {
+        let block_number = block.number.expect("mined blocks have a number").as_u64();
+        let db_txn = self.conn.transaction()?;
+
+        db_txn.execute(
+            "DELETE FROM swap_hops WHERE tx_hash IN (SELECT hash FROM txns WHERE block_number = ?1)",
+            params![block_number],
+        )?;
+        db_txn.execute(
+            "DELETE FROM swaps WHERE tx_hash IN (SELECT hash FROM txns WHERE block_number = ?1)",
+            params![block_number],
+        )?;
+        db_txn.execute(
+            "DELETE FROM txns WHERE block_number = ?1",
+            params![block_number],
+        )?;
+        db_txn.execute(
+            "DELETE FROM blocks WHERE number = ?1",
+            params![block_number],
+        )?;
+        db_txn.execute(
+            "INSERT OR REPLACE INTO progress (id, last_block, last_hash, updated_at)
+             VALUES (0, ?1, ?2, ?3)",
+            params![
+                block_number.saturating_sub(1),
+                format!("{:?}", block.parent_hash),
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        db_txn.commit()?;
+
+        Ok(())
+    }
+}
+
+fn insert_txn(
+    db_txn: &SqlTransaction,
+    txn: &Transaction,
+    record: &TxnRecord,
+    details: &TxnDetails,
+) -> anyhow::Result<()> {
+    let gas_used = details
+        .receipts
+        .get(&txn.hash)
+        .and_then(|receipt| receipt.gas_used)
+        .map(|gas_used| gas_used.to_string());
+    let gas_cost = record
+        .gas
+        .as_ref()
+        .and_then(|gas| gas.cost.as_ref())
+        .map(|cost| cost.raw.clone());
+
+    db_txn.execute(
+        "INSERT OR REPLACE INTO txns (hash, block_number, tx_index, sender, method, status,
+             revert_reason, error, gas_used, gas_cost, record)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            format!("{:?}", record.tx_hash),
+            record.block_number,
+            txn.transaction_index.map(|index| index.as_u64()),
+            format!("{:?}", record.from),
+            record.method,
+            record.status.map(|status| match status {
+                TxnStatus::Success => "success",
+                TxnStatus::Reverted => "reverted",
+            }),
+            record.revert_reason,
+            record.error,
+            gas_used,
+            gas_cost,
+            serde_json::to_string(record).expect("records always serialize"),
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn insert_swap(db_txn: &SqlTransaction, record: &TxnRecord) -> anyhow::Result<()> {
+    let (exact_input, fee_on_transfer, recipient, path, origin, destination) = match &record.action
+    {
+        Some(ActionRecord::Swap {
+            exact_input,
+            fee_on_transfer,
+            recipient,
+            path,
+            origin,
+            destination,
+        }) => (
+            exact_input,
+            fee_on_transfer,
+            recipient,
+            path,
+            origin,
+            destination,
+        ),
+        _ => return Ok(()),
+    };
+    let tx_hash = format!("{:?}", record.tx_hash);
+
+    let hops = record
+        .executed
+        .as_ref()
+        .map(|executed| executed.hops.as_slice())
+        .unwrap_or_default();
+    let executed_in = hops.first().map(|hop| hop.amount_in.raw.clone());
+    let executed_out = record
+        .executed
+        .as_ref()
+        .and_then(|executed| executed.received.as_ref())
+        .or_else(|| hops.last().map(|hop| &hop.amount_out))
+        .map(|amount| amount.raw.clone());
+
+    db_txn.execute(
+        "INSERT OR REPLACE INTO swaps (tx_hash, exact_input, fee_on_transfer, recipient,
+             origin_token, origin_amount, destination_token, destination_amount,
+             executed_in, executed_out)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            tx_hash,
+            exact_input,
+            fee_on_transfer,
+            format!("{:?}", recipient),
+            address_str(&origin.token),
+            origin.raw,
+            address_str(&destination.token),
+            destination.raw,
+            executed_in,
+            executed_out,
+        ],
+    )?;
+
+    // a rewritten swap may have fewer hops than before
+    db_txn.execute("DELETE FROM swap_hops WHERE tx_hash = ?1", params![tx_hash])?;
+    for (hop_index, pair) in path.windows(2).enumerate() {
+        // executed hops run along the path, one per pair
+        let executed = hops.get(hop_index);
+        db_txn.execute(
+            "INSERT INTO swap_hops (tx_hash, hop_index, token_in, token_out, amount_in, amount_out)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                tx_hash,
+                hop_index as u64,
+                address_str(&pair[0]),
+                address_str(&pair[1]),
+                executed.map(|hop| &hop.amount_in.raw),
+                executed.map(|hop| &hop.amount_out.raw),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+// `None` (NULL) for ETH
+fn address_str(token: &TokenRecord) -> Option<String> {
+    token.address.map(|address| format!("{:?}", address))
+}
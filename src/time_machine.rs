@@ -0,0 +1,66 @@
+use anyhow::bail;
+use ethers::prelude::*;
+
+use crate::{checkpoint::Checkpoint, config::BlockStart};
+
+/// Turns the start of a replay into a block number, `head` being the latest
+/// block and `checkpoint` what the sinks last wrote
+pub async fn resolve_start(
+    client: &Provider<Http>,
+    start: BlockStart,
+    head: U64,
+    checkpoint: Option<Checkpoint>,
+) -> anyhow::Result<U64> {
+    match start {
+        BlockStart::Block(block) => Ok(U64::from(block)),
+        BlockStart::PrevBlocks(prev_blocks) => Ok(head.saturating_sub(U64::from(prev_blocks))),
+        BlockStart::Timestamp(timestamp) => find_block_by_timestamp(client, timestamp, head).await,
+        BlockStart::Resume => match checkpoint {
+            Some(checkpoint) => resume_block(client, checkpoint).await,
+            None => bail!("Nothing to resume, no block has been checkpointed yet"),
+        },
+    }
+}
+
+/// The block after `checkpoint`, or the checkpointed block itself when it has
+/// since been reorganized out of the chain. The database sinks rewrite blocks
+/// they have already seen, so replaying one is harmless there.
+async fn resume_block(client: &Provider<Http>, checkpoint: Checkpoint) -> anyhow::Result<U64> {
+    let number = U64::from(checkpoint.number);
+    match client.get_block(number).await? {
+        Some(block) if block.hash == Some(checkpoint.hash) => Ok(number + 1_u64),
+        _ => Ok(number),
+    }
+}
+
+/// Binary searches `0..=head` for the first block mined at or after
+/// `timestamp`, a unix time in seconds. Takes about `log2(head)` block fetches.
+pub async fn find_block_by_timestamp(
+    client: &Provider<Http>,
+    timestamp: u64,
+    head: U64,
+) -> anyhow::Result<U64> {
+    let target = U256::from(timestamp);
+    if get_block_timestamp(client, head).await? < target {
+        bail!("--since {} is after the chain head", timestamp);
+    }
+
+    let (mut low, mut high) = (U64::zero(), head);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if get_block_timestamp(client, mid).await? < target {
+            low = mid + 1_u64;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok(low)
+}
+
+async fn get_block_timestamp(client: &Provider<Http>, number: U64) -> anyhow::Result<U256> {
+    match client.get_block(number).await? {
+        Some(block) => Ok(block.timestamp),
+        None => bail!("Block {} wasn't found", number),
+    }
+}
@@ -1,187 +1,593 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 
-use anyhow::{Ok, Result};
+use ethers::core::abi::AbiDecode;
 use ethers::prelude::*;
-use ethers::utils::hex;
+use ethers::utils::{hex, keccak256};
 use rayon::prelude::*;
+use serde::Serialize;
 use token_list::Token;
 
-use crate::uni_v2_router::UniV2Router;
+use crate::{
+    amount_format::{format_amount, AmountFormat},
+    uni_v2_router::{UniV2Router, UniV2RouterCalls, UNIV2ROUTER_ABI},
+};
 
 pub const UNISWAP_ADDR_STR: &str = "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D";
-pub const AVAILABLE_METHOD_STRS: &[&str] = &[
-    "0x18cbafe5", // SwapExactTokensForEth(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)
-    "0x38ed1739", // SwapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)
-    "0x8803dbee", // SwapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline)
-    "0x4a25d94a", // SwapTokensForExactEth(uint256 amountOut, uint256 amountInMax, address[] path, address to, uint256 deadline)
-    "0x7ff36ab5", // SwapExactEthforTokens(uint256 amountOutMin, address[] path, address to, uint256 deadline)
-    "0xfb3bdb41", // SwapEthforExactTokens(uint256 amountOut, address[] path, address to, uint256 deadline)
-];
-
-type ISwapEthInputs = (U256, Vec<Address>, Address, U256);
-type ISwapTokenInputs = (U256, U256, Vec<Address>, Address, U256);
-
-enum UniTxnInput {
-    SwapEth(ISwapEthInputs),
-    SwapToken(ISwapTokenInputs),
+pub const WETH_ADDR_STR: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+pub const USDC_ADDR_STR: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+pub const UNISWAP_FACTORY_ADDR_STR: &str = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f";
+
+// keccak256 of the UniswapV2Pair creation code, the CREATE2 hash pairs are
+// deployed with
+const PAIR_INIT_CODE_HASH: &str =
+    "96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f";
+
+// LP tokens minted by UniswapV2Pair always use 18 decimals
+pub const LP_TOKEN_DECIMALS: usize = 18;
+
+/// Why a transaction sent to the router couldn't be turned into a `UniRouterAction`
+#[derive(Debug)]
+pub enum DecodeError {
+    /// calldata is too short to hold a 4 byte selector
+    MissingSelector(usize),
+    /// selector doesn't belong to any router function
+    UnknownSelector(String),
+    /// selector matched but the arguments didn't decode
+    InvalidCalldata { method: String, reason: String },
+    /// router function that doesn't move funds (views, `WETH()`, ...)
+    UnsupportedMethod(String),
+    /// swap with an empty `path`
+    EmptyPath(String),
 }
 
-#[derive(PartialEq)]
-enum UniTxnMethod {
-    Eth(EthTxnMethod),
-    Token(TokenTxnMethod),
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::MissingSelector(len) => {
+                write!(f, "calldata is {} bytes, too short for a selector", len)
+            }
+            DecodeError::UnknownSelector(selector) => {
+                write!(f, "unknown router selector {}", selector)
+            }
+            DecodeError::InvalidCalldata { method, reason } => {
+                write!(f, "malformed {} calldata: {}", method, reason)
+            }
+            DecodeError::UnsupportedMethod(method) => {
+                write!(f, "{} is not a swap or liquidity call", method)
+            }
+            DecodeError::EmptyPath(method) => write!(f, "{} has an empty swap path", method),
+        }
+    }
 }
 
-#[derive(PartialEq)]
-enum EthTxnMethod {
-    SwapEthForExactTokens,
-    SwapExactEthForTokens,
+impl std::error::Error for DecodeError {}
+
+/// A router transaction alongside its decoded call
+pub type DecodedTxn<'a> = (&'a Transaction, Result<UniRouterAction, DecodeError>);
+
+/// A decoded router call, either a swap or a change in pool liquidity
+pub enum UniRouterAction {
+    Swap(UniTxnInputs),
+    Liquidity(UniLiquidityInputs),
 }
 
-#[derive(PartialEq)]
-enum TokenTxnMethod {
-    SwapExactTokensForEth,
-    SwapExactTokensForTokens,
-    SwapTokensForExactTokens,
-    SwapTokensForExactEth,
+impl UniRouterAction {
+    pub fn try_new(txn: &Transaction) -> Result<UniRouterAction, DecodeError> {
+        UniRouterAction::try_from_calldata(&txn.input, txn.value)
+    }
+
+    /// Decodes raw router calldata, `value` being the ETH sent along with it
+    pub fn try_from_calldata(input: &Bytes, value: U256) -> Result<UniRouterAction, DecodeError> {
+        let call = decode_router_call(input)?;
+        if let Some(inputs) = UniTxnInputs::from_call(&call, value) {
+            return inputs
+                .map(UniRouterAction::Swap)
+                .ok_or_else(|| DecodeError::EmptyPath(router_method_name(input)));
+        }
+        UniLiquidityInputs::from_call(&call, value)
+            .map(UniRouterAction::Liquidity)
+            .ok_or_else(|| DecodeError::UnsupportedMethod(router_method_name(input)))
+    }
+
+    pub fn log_str(
+        &self,
+        token_map: &HashMap<String, Token>,
+        amount_format: &AmountFormat,
+    ) -> String {
+        match self {
+            UniRouterAction::Swap(inputs) => inputs.log_str(token_map, amount_format),
+            UniRouterAction::Liquidity(inputs) => inputs.log_str(token_map, amount_format),
+        }
+    }
 }
 
 pub struct UniTxnInputs {
+    path: Vec<Address>,
+    recipient: Address,
+    // exact input swaps bound the destination amount from below (amountOutMin),
+    // exact output swaps bound the origin amount from above (amountInMax)
+    exact_input: bool,
     origin_address: Option<Address>,
     origin_amount: U256,
     destination_address: Option<Address>,
     destination_amount: U256,
+    fee_on_transfer: bool,
 }
 
 impl UniTxnInputs {
-    pub fn new<T>(
-        txn: &Transaction,
-        uniswap_router_contract: &UniV2Router<Provider<T>>,
-    ) -> UniTxnInputs
-    where
-        T: JsonRpcClient,
-    {
-        let (txn_inputs, method) = decode_txn_inputs(txn, uniswap_router_contract).unwrap();
-        match txn_inputs {
-            UniTxnInput::SwapEth(inputs) => {
-                let origin_address = None;
-                let destination_address = Some(*inputs.1.last().unwrap());
-                let destination_amount = inputs.0;
-                let origin_amount = txn.value;
-                Self {
-                    origin_amount,
-                    origin_address,
-                    destination_amount,
-                    destination_address,
-                }
+    // `None` if the call isn't a swap, `Some(None)` if it is but has no path
+    fn from_call(call: &UniV2RouterCalls, value: U256) -> Option<Option<Self>> {
+        let inputs = match call {
+            UniV2RouterCalls::SwapExactETHForTokens(c) => {
+                Self::exact_in(&c.path, c.to, value, c.amount_out_min).map(Self::eth_in)
             }
-            UniTxnInput::SwapToken(inputs) => match method {
-                UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForTokens)
-                | UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForEth) => {
-                    let destination_address: Option<Address> = match method {
-                        UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForEth) => None,
-                        UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForTokens) => {
-                            Some(*inputs.2.last().unwrap() as Address)
-                        }
-                        _ => panic!("Hit an impossible catch all"),
-                    };
-
-                    let origin_address: Option<Address> = match method {
-                        UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForTokens)
-                        | UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForEth) => {
-                            Some(*inputs.2.get(0).unwrap() as Address)
-                        }
-                        _ => panic!("Hit an impossible catch all"),
-                    };
-
-                    Self {
-                        destination_address,
-                        destination_amount: inputs.1,
-                        origin_address,
-                        origin_amount: inputs.0,
-                    }
-                }
-                UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactTokens)
-                | UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactEth) => {
-                    let destination_address: Option<Address> = match method {
-                        UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactEth) => None,
-                        UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactTokens) => {
-                            Some(*inputs.2.last().unwrap() as Address)
-                        }
-                        _ => panic!("Hit an impossible catch all"),
-                    };
-
-                    let origin_address: Option<Address> = match method {
-                        UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactTokens)
-                        | UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactEth) => {
-                            Some(*inputs.2.get(0).unwrap() as Address)
-                        }
-                        _ => panic!("Hit an impossible catch all"),
-                    };
-
-                    Self {
-                        destination_address,
-                        destination_amount: inputs.0,
-                        origin_address,
-                        origin_amount: inputs.1,
-                    }
-                }
-                _ => panic!("We failed"),
-            },
+            UniV2RouterCalls::SwapETHForExactTokens(c) => {
+                Self::exact_out(&c.path, c.to, value, c.amount_out).map(Self::eth_in)
+            }
+            UniV2RouterCalls::SwapExactETHForTokensSupportingFeeOnTransferTokens(c) => {
+                Self::exact_in(&c.path, c.to, value, c.amount_out_min)
+                    .map(Self::eth_in)
+                    .map(Self::supporting_fee_on_transfer)
+            }
+            UniV2RouterCalls::SwapExactTokensForETH(c) => {
+                Self::exact_in(&c.path, c.to, c.amount_in, c.amount_out_min).map(Self::eth_out)
+            }
+            UniV2RouterCalls::SwapExactTokensForETHSupportingFeeOnTransferTokens(c) => {
+                Self::exact_in(&c.path, c.to, c.amount_in, c.amount_out_min)
+                    .map(Self::eth_out)
+                    .map(Self::supporting_fee_on_transfer)
+            }
+            UniV2RouterCalls::SwapExactTokensForTokens(c) => {
+                Self::exact_in(&c.path, c.to, c.amount_in, c.amount_out_min)
+            }
+            UniV2RouterCalls::SwapExactTokensForTokensSupportingFeeOnTransferTokens(c) => {
+                Self::exact_in(&c.path, c.to, c.amount_in, c.amount_out_min)
+                    .map(Self::supporting_fee_on_transfer)
+            }
+            UniV2RouterCalls::SwapTokensForExactETH(c) => {
+                Self::exact_out(&c.path, c.to, c.amount_in_max, c.amount_out).map(Self::eth_out)
+            }
+            UniV2RouterCalls::SwapTokensForExactTokens(c) => {
+                Self::exact_out(&c.path, c.to, c.amount_in_max, c.amount_out)
+            }
+            _ => return None,
+        };
+        Some(inputs)
+    }
+
+    // a token to token swap of `amount_in` for at least `amount_out_min`
+    fn exact_in(
+        path: &[Address],
+        recipient: Address,
+        amount_in: U256,
+        amount_out_min: U256,
+    ) -> Option<Self> {
+        Self::swap(path, recipient, true, amount_in, amount_out_min)
+    }
+
+    // a token to token swap of at most `amount_in_max` for `amount_out`
+    fn exact_out(
+        path: &[Address],
+        recipient: Address,
+        amount_in_max: U256,
+        amount_out: U256,
+    ) -> Option<Self> {
+        Self::swap(path, recipient, false, amount_in_max, amount_out)
+    }
+
+    fn swap(
+        path: &[Address],
+        recipient: Address,
+        exact_input: bool,
+        origin_amount: U256,
+        destination_amount: U256,
+    ) -> Option<Self> {
+        Some(Self {
+            path: path.to_vec(),
+            recipient,
+            exact_input,
+            origin_address: Some(*path.first()?),
+            origin_amount,
+            destination_address: Some(*path.last()?),
+            destination_amount,
+            fee_on_transfer: false,
+        })
+    }
+
+    // the ETH side of a swap is routed through WETH, so it is reported as
+    // `None` rather than the WETH address at that end of the path
+    fn eth_in(self) -> Self {
+        Self {
+            origin_address: None,
+            ..self
+        }
+    }
+
+    fn eth_out(self) -> Self {
+        Self {
+            destination_address: None,
+            ..self
+        }
+    }
+
+    fn supporting_fee_on_transfer(self) -> Self {
+        Self {
+            fee_on_transfer: true,
+            ..self
         }
     }
 
-    pub fn log_str(&self, token_map: &HashMap<String, Token>) -> String {
-        let origin_str =
-            UniTxnInputs::build_side_str(&self.origin_amount, &self.origin_address, token_map);
-        let destination_str = UniTxnInputs::build_side_str(
+    pub fn log_str(
+        &self,
+        token_map: &HashMap<String, Token>,
+        amount_format: &AmountFormat,
+    ) -> String {
+        let origin_str = build_side_str(
+            &self.origin_amount,
+            &self.origin_address,
+            token_map,
+            amount_format,
+        );
+        let destination_str = build_side_str(
             &self.destination_amount,
             &self.destination_address,
             token_map,
+            amount_format,
         );
 
-        format!("Swap {} for {}", origin_str, destination_str)
+        let label = if self.fee_on_transfer {
+            "Swap (fee-on-transfer)"
+        } else {
+            "Swap"
+        };
+
+        format!(
+            "{} {} for {} via {}",
+            label,
+            origin_str,
+            destination_str,
+            self.path_str(token_map)
+        )
+    }
+
+    pub fn path(&self) -> &[Address] {
+        &self.path
+    }
+
+    pub fn recipient(&self) -> Address {
+        self.recipient
+    }
+
+    pub fn exact_input(&self) -> bool {
+        self.exact_input
+    }
+
+    /// `None` when the swap is paid for in ETH
+    pub fn origin_address(&self) -> Option<Address> {
+        self.origin_address
+    }
+
+    pub fn origin_amount(&self) -> U256 {
+        self.origin_amount
+    }
+
+    pub fn destination_amount(&self) -> U256 {
+        self.destination_amount
+    }
+
+    /// `None` when the swap pays out ETH
+    pub fn destination_address(&self) -> Option<Address> {
+        self.destination_address
+    }
+
+    pub fn fee_on_transfer(&self) -> bool {
+        self.fee_on_transfer
+    }
+
+    /// Every hop of the route, e.g. `USDC ➜ WETH ➜ PEPE`
+    pub fn path_str(&self, token_map: &HashMap<String, Token>) -> String {
+        self.path
+            .iter()
+            .map(|address| build_token_str(address, token_map))
+            .collect::<Vec<String>>()
+            .join(" ➜ ")
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LiquidityDirection {
+    Add,
+    Remove,
+}
+
+/// Inputs of an addLiquidity / removeLiquidity router call. A `None` token is
+/// the ETH side of the `*ETH` variants.
+pub struct UniLiquidityInputs {
+    direction: LiquidityDirection,
+    token_a: Option<Address>,
+    token_b: Option<Address>,
+    amount_a_desired: Option<U256>,
+    amount_b_desired: Option<U256>,
+    amount_a_min: U256,
+    amount_b_min: U256,
+    liquidity: Option<U256>,
+    recipient: Address,
+    permit: bool,
+    fee_on_transfer: bool,
+}
+
+impl UniLiquidityInputs {
+    fn from_call(call: &UniV2RouterCalls, value: U256) -> Option<Self> {
+        let inputs = match call {
+            UniV2RouterCalls::AddLiquidity(c) => Self {
+                direction: LiquidityDirection::Add,
+                token_a: Some(c.token_a),
+                token_b: Some(c.token_b),
+                amount_a_desired: Some(c.amount_a_desired),
+                amount_b_desired: Some(c.amount_b_desired),
+                amount_a_min: c.amount_a_min,
+                amount_b_min: c.amount_b_min,
+                liquidity: None,
+                recipient: c.to,
+                permit: false,
+                fee_on_transfer: false,
+            },
+            UniV2RouterCalls::AddLiquidityETH(c) => Self {
+                direction: LiquidityDirection::Add,
+                token_a: Some(c.token),
+                token_b: None,
+                amount_a_desired: Some(c.amount_token_desired),
+                amount_b_desired: Some(value),
+                amount_a_min: c.amount_token_min,
+                amount_b_min: c.amount_eth_min,
+                liquidity: None,
+                recipient: c.to,
+                permit: false,
+                fee_on_transfer: false,
+            },
+            UniV2RouterCalls::RemoveLiquidity(c) => Self::remove(
+                Some(c.token_a),
+                Some(c.token_b),
+                c.liquidity,
+                c.amount_a_min,
+                c.amount_b_min,
+                c.to,
+            ),
+            UniV2RouterCalls::RemoveLiquidityWithPermit(c) => Self::remove(
+                Some(c.token_a),
+                Some(c.token_b),
+                c.liquidity,
+                c.amount_a_min,
+                c.amount_b_min,
+                c.to,
+            )
+            .with_permit(),
+            UniV2RouterCalls::RemoveLiquidityETH(c) => Self::remove(
+                Some(c.token),
+                None,
+                c.liquidity,
+                c.amount_token_min,
+                c.amount_eth_min,
+                c.to,
+            ),
+            UniV2RouterCalls::RemoveLiquidityETHSupportingFeeOnTransferTokens(c) => Self::remove(
+                Some(c.token),
+                None,
+                c.liquidity,
+                c.amount_token_min,
+                c.amount_eth_min,
+                c.to,
+            )
+            .supporting_fee_on_transfer(),
+            UniV2RouterCalls::RemoveLiquidityETHWithPermit(c) => Self::remove(
+                Some(c.token),
+                None,
+                c.liquidity,
+                c.amount_token_min,
+                c.amount_eth_min,
+                c.to,
+            )
+            .with_permit(),
+            UniV2RouterCalls::RemoveLiquidityETHWithPermitSupportingFeeOnTransferTokens(c) => {
+                Self::remove(
+                    Some(c.token),
+                    None,
+                    c.liquidity,
+                    c.amount_token_min,
+                    c.amount_eth_min,
+                    c.to,
+                )
+                .with_permit()
+                .supporting_fee_on_transfer()
+            }
+            _ => return None,
+        };
+        Some(inputs)
+    }
+
+    fn remove(
+        token_a: Option<Address>,
+        token_b: Option<Address>,
+        liquidity: U256,
+        amount_a_min: U256,
+        amount_b_min: U256,
+        recipient: Address,
+    ) -> Self {
+        Self {
+            direction: LiquidityDirection::Remove,
+            token_a,
+            token_b,
+            amount_a_desired: None,
+            amount_b_desired: None,
+            amount_a_min,
+            amount_b_min,
+            liquidity: Some(liquidity),
+            recipient,
+            permit: false,
+            fee_on_transfer: false,
+        }
+    }
+
+    // the LP tokens are approved with a signature in the same call
+    fn with_permit(self) -> Self {
+        Self {
+            permit: true,
+            ..self
+        }
+    }
+
+    fn supporting_fee_on_transfer(self) -> Self {
+        Self {
+            fee_on_transfer: true,
+            ..self
+        }
+    }
+
+    pub fn direction(&self) -> LiquidityDirection {
+        self.direction
+    }
+
+    /// `None` for the ETH side
+    pub fn token_a(&self) -> Option<Address> {
+        self.token_a
+    }
+
+    /// `None` for the ETH side
+    pub fn token_b(&self) -> Option<Address> {
+        self.token_b
+    }
+
+    /// `None` for removals
+    pub fn amount_a_desired(&self) -> Option<U256> {
+        self.amount_a_desired
     }
 
-    fn build_side_str(
-        amount: &U256,
-        address: &Option<Address>,
+    /// `None` for removals
+    pub fn amount_b_desired(&self) -> Option<U256> {
+        self.amount_b_desired
+    }
+
+    pub fn amount_a_min(&self) -> U256 {
+        self.amount_a_min
+    }
+
+    pub fn amount_b_min(&self) -> U256 {
+        self.amount_b_min
+    }
+
+    /// LP tokens burnt, `None` for additions
+    pub fn liquidity(&self) -> Option<U256> {
+        self.liquidity
+    }
+
+    pub fn recipient(&self) -> Address {
+        self.recipient
+    }
+
+    pub fn permit(&self) -> bool {
+        self.permit
+    }
+
+    pub fn fee_on_transfer(&self) -> bool {
+        self.fee_on_transfer
+    }
+
+    pub fn log_str(
+        &self,
         token_map: &HashMap<String, Token>,
+        amount_format: &AmountFormat,
     ) -> String {
-        match address {
-            Some(a) => {
-                let address = format!("0x{}", hex::encode(a));
-                match token_map.get(&address) {
-                    Some(t) => {
-                        format!(
-                            "{} {}",
-                            UniTxnInputs::parse_u256_to_f64(amount, t.decimals as usize),
-                            t.symbol
-                        )
-                        // todo format amount
-                    }
-                    None => {
-                        format!("{} {}", amount, address)
-                    }
-                }
+        let min_a_str = build_side_str(&self.amount_a_min, &self.token_a, token_map, amount_format);
+        let min_b_str = build_side_str(&self.amount_b_min, &self.token_b, token_map, amount_format);
+
+        let mut log_str = match self.direction {
+            LiquidityDirection::Add => {
+                let desired_a_str = build_side_str(
+                    &self.amount_a_desired.unwrap_or_default(),
+                    &self.token_a,
+                    token_map,
+                    amount_format,
+                );
+                let desired_b_str = build_side_str(
+                    &self.amount_b_desired.unwrap_or_default(),
+                    &self.token_b,
+                    token_map,
+                    amount_format,
+                );
+                format!(
+                    "Add {} + {} (min {} + {})",
+                    desired_a_str, desired_b_str, min_a_str, min_b_str
+                )
             }
-            None => UniTxnInputs::build_eth_str(amount),
+            LiquidityDirection::Remove => format!(
+                "Remove {} LP for min {} + {}",
+                format_amount(
+                    &self.liquidity.unwrap_or_default(),
+                    LP_TOKEN_DECIMALS,
+                    amount_format
+                ),
+                min_a_str,
+                min_b_str
+            ),
+        };
+
+        log_str.push_str(&format!(" to 0x{}", hex::encode(self.recipient)));
+        if self.permit {
+            log_str.push_str(" (permit)");
+        }
+        if self.fee_on_transfer {
+            log_str.push_str(" (fee-on-transfer)");
         }
+
+        log_str
     }
+}
 
-    fn build_eth_str(amount: &U256) -> String {
-        format!("{} ETH", UniTxnInputs::parse_u256_to_f64(amount, 18))
+pub fn build_side_str(
+    amount: &U256,
+    address: &Option<Address>,
+    token_map: &HashMap<String, Token>,
+    amount_format: &AmountFormat,
+) -> String {
+    match address {
+        Some(a) => {
+            let address = address_key(a);
+            match token_map.get(&address) {
+                Some(t) => {
+                    format!(
+                        "{} {}",
+                        format_amount(amount, t.decimals as usize, amount_format),
+                        t.symbol
+                    )
+                }
+                None => {
+                    format!("{} {}", amount, address)
+                }
+            }
+        }
+        None => build_eth_str(amount, amount_format),
     }
+}
 
-    fn parse_u256_to_f64(amount: &U256, decimals: usize) -> f64 {
-        // todo handle overflow casting to u128
-        let mut padded_str = format!("{:0decimals$}", amount.as_u128(), decimals = decimals + 1);
+// token symbol when the token list knows it, the address otherwise
+fn build_token_str(address: &Address, token_map: &HashMap<String, Token>) -> String {
+    let address = address_key(address);
+    match token_map.get(&address) {
+        Some(t) => t.symbol.clone(),
+        None => address,
+    }
+}
 
-        padded_str.insert(padded_str.len() - decimals, '.');
+/// Key of `address` in the token map, lowercase `0x` hex
+pub fn address_key(address: &Address) -> String {
+    format!("0x{}", hex::encode(address))
+}
 
-        padded_str.parse::<f64>().unwrap()
-    }
+fn build_eth_str(amount: &U256, amount_format: &AmountFormat) -> String {
+    format!("{} ETH", format_amount(amount, 18, amount_format))
 }
 
 pub fn get_uniswap_router_contract<T>(client: Arc<Provider<T>>) -> UniV2Router<Provider<T>>
@@ -194,20 +600,74 @@ where
     UniV2Router::new(address, client)
 }
 
+/// Address of the factory's pair for `token_a` and `token_b`, computed
+/// offline like `UniswapV2Library.pairFor`
+pub fn pair_for(token_a: Address, token_b: Address) -> Address {
+    let (token0, token1) = if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    };
+    let factory = UNISWAP_FACTORY_ADDR_STR
+        .parse::<Address>()
+        .expect("Can't parse string to H160");
+
+    let mut preimage = vec![0xff];
+    preimage.extend_from_slice(factory.as_bytes());
+    preimage.extend_from_slice(&keccak256([token0.as_bytes(), token1.as_bytes()].concat()));
+    preimage.extend(hex::decode(PAIR_INIT_CODE_HASH).expect("the init code hash is hex"));
+    Address::from_slice(&keccak256(preimage)[12..])
+}
+
+pub fn decode_txns<'a>(txns: &[&'a Transaction]) -> Vec<DecodedTxn<'a>> {
+    txns.iter()
+        .map(|txn| (*txn, UniRouterAction::try_new(txn)))
+        .collect()
+}
+
+/// Decodes router calldata into the matching generated call type, using the
+/// 4 byte selector to pick the function
+pub fn decode_router_call(input: &Bytes) -> Result<UniV2RouterCalls, DecodeError> {
+    if input.len() < 4 {
+        return Err(DecodeError::MissingSelector(input.len()));
+    }
+    UniV2RouterCalls::decode(input).map_err(|err| {
+        let method = router_method_name(input);
+        if method.starts_with("0x") {
+            DecodeError::UnknownSelector(method)
+        } else {
+            DecodeError::InvalidCalldata {
+                method,
+                reason: err.to_string(),
+            }
+        }
+    })
+}
+
+/// Name of the router function matching the calldata selector, falls back to
+/// the hex selector when the ABI doesn't know it
+pub fn router_method_name(input: &Bytes) -> String {
+    let selector = &input[..4.min(input.len())];
+    UNIV2ROUTER_ABI
+        .functions()
+        .find(|function| function.short_signature() == selector)
+        .map(|function| function.name.clone())
+        .unwrap_or_else(|| format!("0x{}", hex::encode(selector)))
+}
+
 pub fn filter_uni_txns(full_block: &Block<Transaction>) -> Vec<&Transaction> {
     full_block
         .transactions
         .par_iter()
         .filter(|txn| {
-            // filters if uniswap is to address,
-            // filters if method is one we can handle
+            // filters if uniswap is to address, calls we can't decode are
+            // kept so they can be reported instead of silently dropped
             let is_uniswap_txn: bool = match txn.to {
                 Some(to_address) => {
                     let uniswap_addr = UNISWAP_ADDR_STR
                         .parse::<H160>()
                         .expect("Can't parse string to H160");
-                    let to_uniswap = to_address == uniswap_addr;
-                    to_uniswap && AVAILABLE_METHOD_STRS.contains(&&txn.input.to_string()[0..10])
+                    to_address == uniswap_addr
                 }
                 None => false,
             };
@@ -216,64 +676,248 @@ pub fn filter_uni_txns(full_block: &Block<Transaction>) -> Vec<&Transaction> {
         .collect()
 }
 
-fn decode_txn_method(txn: &Transaction) -> Option<UniTxnMethod> {
-    let method_str = &txn.input.to_string()[0..10];
-    match method_str {
-        "0x7ff36ab5" => Some(UniTxnMethod::Eth(EthTxnMethod::SwapExactEthForTokens)),
-        "0xfb3bdb41" => Some(UniTxnMethod::Eth(EthTxnMethod::SwapEthForExactTokens)),
-        "0x18cbafe5" => Some(UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForEth)),
-        "0x38ed1739" => Some(UniTxnMethod::Token(
-            TokenTxnMethod::SwapExactTokensForTokens,
-        )),
-        "0x8803dbee" => Some(UniTxnMethod::Token(
-            TokenTxnMethod::SwapTokensForExactTokens,
-        )),
-        "0x4a25d94a" => Some(UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactEth)),
-        _ => None,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEPE_ADDR_STR: &str = "0x6982508145454Ce325dDbE47a25d4ec3d2311933";
+    const RECIPIENT_ADDR_STR: &str = "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045";
+
+    fn address(address: &str) -> Address {
+        address.parse().unwrap()
     }
-}
 
-fn decode_txn_inputs<T>(
-    txn: &Transaction,
-    uniswap_router_contract: &UniV2Router<Provider<T>>,
-) -> Result<(UniTxnInput, UniTxnMethod)>
-where
-    T: JsonRpcClient,
-{
-    let txn_method = decode_txn_method(txn).expect("Trying to decode an unsupported method");
-
-    let txn_inputs = match txn_method {
-        UniTxnMethod::Eth(EthTxnMethod::SwapExactEthForTokens) => UniTxnInput::SwapEth(
-            uniswap_router_contract
-                .decode("swapExactETHForTokens", &txn.input)
-                .unwrap(),
-        ),
-        UniTxnMethod::Eth(EthTxnMethod::SwapEthForExactTokens) => UniTxnInput::SwapEth(
-            uniswap_router_contract
-                .decode("swapETHForExactTokens", &txn.input)
-                .unwrap(),
-        ),
-        UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForEth) => UniTxnInput::SwapToken(
-            uniswap_router_contract
-                .decode("swapExactTokensForETH", &txn.input)
-                .unwrap(),
-        ),
-        UniTxnMethod::Token(TokenTxnMethod::SwapExactTokensForTokens) => UniTxnInput::SwapToken(
-            uniswap_router_contract
-                .decode("swapExactTokensForTokens", &txn.input)
-                .unwrap(),
-        ),
-        UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactTokens) => UniTxnInput::SwapToken(
-            uniswap_router_contract
-                .decode("swapTokensForExactTokens", &txn.input)
-                .unwrap(),
-        ),
-        UniTxnMethod::Token(TokenTxnMethod::SwapTokensForExactEth) => UniTxnInput::SwapToken(
-            uniswap_router_contract
-                .decode("swapTokensForExactETH", &txn.input)
-                .unwrap(),
-        ),
-    };
+    fn decode(calldata: &str, value: U256) -> Result<UniRouterAction, DecodeError> {
+        let input = Bytes::from(hex::decode(calldata).unwrap());
+        UniRouterAction::try_from_calldata(&input, value)
+    }
+
+    fn decode_swap(calldata: &str, value: U256) -> UniTxnInputs {
+        match decode(calldata, value) {
+            Ok(UniRouterAction::Swap(inputs)) => inputs,
+            Ok(UniRouterAction::Liquidity(_)) => panic!("decoded a liquidity call"),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn decode_liquidity(calldata: &str, value: U256) -> UniLiquidityInputs {
+        match decode(calldata, value) {
+            Ok(UniRouterAction::Liquidity(inputs)) => inputs,
+            Ok(UniRouterAction::Swap(_)) => panic!("decoded a swap"),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn decode_err(calldata: &str) -> DecodeError {
+        match decode(calldata, U256::zero()) {
+            Ok(_) => panic!("{} decoded", calldata),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn pair_for_matches_the_deployed_pair() {
+        let weth_usdc = address("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+        let (weth, usdc) = (address(WETH_ADDR_STR), address(USDC_ADDR_STR));
+        assert_eq!(pair_for(weth, usdc), weth_usdc);
+        assert_eq!(pair_for(usdc, weth), weth_usdc);
+    }
+
+    #[test]
+    fn decodes_eth_for_fee_on_transfer_tokens() {
+        // swapExactETHForTokensSupportingFeeOnTransferTokens
+        let calldata = concat!(
+            "b6f9de95",
+            "00000000000000000000000000000000000000000000d3c21bcecceda1000000",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "000000000000000000000000000000000000000000000000000000006abda280",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "0000000000000000000000006982508145454ce325ddbe47a25d4ec3d2311933",
+        );
+        let inputs = decode_swap(calldata, U256::exp10(18));
+        assert!(inputs.fee_on_transfer());
+        assert!(inputs.exact_input());
+        assert_eq!(inputs.origin_address(), None);
+        assert_eq!(inputs.origin_amount(), U256::exp10(18));
+        assert_eq!(inputs.destination_address(), Some(address(PEPE_ADDR_STR)));
+        assert_eq!(inputs.destination_amount(), U256::exp10(24));
+        assert_eq!(
+            inputs.path(),
+            [address(WETH_ADDR_STR), address(PEPE_ADDR_STR)]
+        );
+        assert_eq!(inputs.recipient(), address(RECIPIENT_ADDR_STR));
+    }
+
+    #[test]
+    fn decodes_fee_on_transfer_tokens_for_eth() {
+        // swapExactTokensForETHSupportingFeeOnTransferTokens
+        let calldata = concat!(
+            "791ac947",
+            "00000000000000000000000000000000000000000000d3c21bcecceda1000000",
+            "000000000000000000000000000000000000000000000000016345785d8a0000",
+            "00000000000000000000000000000000000000000000000000000000000000a0",
+            "000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "000000000000000000000000000000000000000000000000000000006abda280",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000006982508145454ce325ddbe47a25d4ec3d2311933",
+            "000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+        );
+        let inputs = decode_swap(calldata, U256::zero());
+        assert!(inputs.fee_on_transfer());
+        assert!(inputs.exact_input());
+        assert_eq!(inputs.origin_address(), Some(address(PEPE_ADDR_STR)));
+        assert_eq!(inputs.origin_amount(), U256::exp10(24));
+        assert_eq!(inputs.destination_address(), None);
+        assert_eq!(inputs.destination_amount(), U256::exp10(17));
+        assert_eq!(
+            inputs.path(),
+            [address(PEPE_ADDR_STR), address(WETH_ADDR_STR)]
+        );
+    }
+
+    #[test]
+    fn decodes_fee_on_transfer_tokens_for_tokens() {
+        // swapExactTokensForTokensSupportingFeeOnTransferTokens
+        let calldata = concat!(
+            "5c11d795",
+            "00000000000000000000000000000000000000000000d3c21bcecceda1000000",
+            "0000000000000000000000000000000000000000000000000000000005f5e100",
+            "00000000000000000000000000000000000000000000000000000000000000a0",
+            "000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "000000000000000000000000000000000000000000000000000000006abda280",
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "0000000000000000000000006982508145454ce325ddbe47a25d4ec3d2311933",
+            "000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        );
+        let inputs = decode_swap(calldata, U256::zero());
+        assert!(inputs.fee_on_transfer());
+        assert!(inputs.exact_input());
+        assert_eq!(inputs.origin_address(), Some(address(PEPE_ADDR_STR)));
+        assert_eq!(inputs.destination_address(), Some(address(USDC_ADDR_STR)));
+        assert_eq!(inputs.destination_amount(), U256::from(100_000_000_u64));
+        assert_eq!(
+            inputs.path(),
+            [
+                address(PEPE_ADDR_STR),
+                address(WETH_ADDR_STR),
+                address(USDC_ADDR_STR)
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_add_liquidity_eth() {
+        // addLiquidityETH
+        let calldata = concat!(
+            "f305d719",
+            "0000000000000000000000006982508145454ce325ddbe47a25d4ec3d2311933",
+            "00000000000000000000000000000000000000000000d3c21bcecceda1000000",
+            "00000000000000000000000000000000000000000000d1a401ee0332eec00000",
+            "000000000000000000000000000000000000000000000000016345785d8a0000",
+            "000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "000000000000000000000000000000000000000000000000000000006abda280",
+        );
+        let inputs = decode_liquidity(calldata, U256::exp10(17) * 2);
+        assert!(matches!(inputs.direction(), LiquidityDirection::Add));
+        assert_eq!(inputs.token_a(), Some(address(PEPE_ADDR_STR)));
+        assert_eq!(inputs.token_b(), None);
+        assert_eq!(inputs.amount_a_desired(), Some(U256::exp10(24)));
+        assert_eq!(inputs.amount_b_desired(), Some(U256::exp10(17) * 2));
+        assert_eq!(inputs.amount_a_min(), U256::exp10(22) * 99);
+        assert_eq!(inputs.amount_b_min(), U256::exp10(17));
+        assert_eq!(inputs.liquidity(), None);
+        assert_eq!(inputs.recipient(), address(RECIPIENT_ADDR_STR));
+        assert!(!inputs.permit());
+        assert!(!inputs.fee_on_transfer());
+    }
+
+    #[test]
+    fn decodes_remove_liquidity() {
+        // removeLiquidity
+        let calldata = concat!(
+            "baa2abde",
+            "000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            "000000000000000000000000c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            "000000000000000000000000000000000000000000000000000000e8d4a51000",
+            "000000000000000000000000000000000000000000000000000000003b9aca00",
+            "00000000000000000000000000000000000000000000000006f05b59d3b20000",
+            "000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "000000000000000000000000000000000000000000000000000000006abda280",
+        );
+        let inputs = decode_liquidity(calldata, U256::zero());
+        assert!(matches!(inputs.direction(), LiquidityDirection::Remove));
+        assert_eq!(inputs.token_a(), Some(address(USDC_ADDR_STR)));
+        assert_eq!(inputs.token_b(), Some(address(WETH_ADDR_STR)));
+        assert_eq!(inputs.amount_a_desired(), None);
+        assert_eq!(inputs.amount_b_desired(), None);
+        assert_eq!(inputs.amount_a_min(), U256::from(1_000_000_000_u64));
+        assert_eq!(inputs.amount_b_min(), U256::exp10(17) * 5);
+        assert_eq!(inputs.liquidity(), Some(U256::exp10(12)));
+        assert!(!inputs.permit());
+        assert!(!inputs.fee_on_transfer());
+    }
+
+    #[test]
+    fn short_calldata_has_no_selector() {
+        assert!(matches!(decode_err(""), DecodeError::MissingSelector(0)));
+        assert!(matches!(
+            decode_err("7ff362"),
+            DecodeError::MissingSelector(3)
+        ));
+    }
+
+    #[test]
+    fn unknown_selector_is_reported_in_hex() {
+        let calldata = concat!(
+            "deadbeef",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        );
+        match decode_err(calldata) {
+            DecodeError::UnknownSelector(selector) => assert_eq!(selector, "0xdeadbeef"),
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn truncated_arguments_are_invalid_calldata() {
+        // removeLiquidity cut off after tokenA
+        let calldata = concat!(
+            "baa2abde",
+            "000000000000000000000000a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        );
+        match decode_err(calldata) {
+            DecodeError::InvalidCalldata { method, .. } => assert_eq!(method, "removeLiquidity"),
+            err => panic!("{}", err),
+        }
+    }
 
-    Ok((txn_inputs, txn_method))
+    #[test]
+    fn views_are_unsupported() {
+        // WETH()
+        match decode_err("ad5c4648") {
+            DecodeError::UnsupportedMethod(method) => assert_eq!(method, "WETH"),
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn swaps_need_a_path() {
+        // swapExactTokensForTokens with an empty path
+        let calldata = concat!(
+            "38ed1739",
+            "0000000000000000000000000000000000000000000000000de0b6b3a7640000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000a0",
+            "000000000000000000000000d8da6bf26964af9d7eed9e03e53415d37aa96045",
+            "000000000000000000000000000000000000000000000000000000006abda280",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        );
+        match decode_err(calldata) {
+            DecodeError::EmptyPath(method) => assert_eq!(method, "swapExactTokensForTokens"),
+            err => panic!("{}", err),
+        }
+    }
 }
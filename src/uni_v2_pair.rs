@@ -0,0 +1,44 @@
+//! The subset of UniswapV2Pair events needed to read what a router call
+//! actually executed. `Transfer` is the plain ERC20 event, so it also matches
+//! transfers emitted by the tokens on either side of a pair.
+use ethers::contract::{EthEvent, EthLogDecode};
+use ethers::core::abi::RawLog;
+use ethers::prelude::*;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, EthEvent)]
+#[ethevent(
+    name = "Swap",
+    abi = "Swap(address,uint256,uint256,uint256,uint256,address)"
+)]
+pub struct SwapFilter {
+    #[ethevent(indexed)]
+    pub sender: Address,
+    pub amount_0_in: U256,
+    pub amount_1_in: U256,
+    pub amount_0_out: U256,
+    pub amount_1_out: U256,
+    #[ethevent(indexed)]
+    pub to: Address,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, EthEvent)]
+#[ethevent(name = "Transfer", abi = "Transfer(address,address,uint256)")]
+pub struct TransferFilter {
+    #[ethevent(indexed)]
+    pub from: Address,
+    #[ethevent(indexed)]
+    pub to: Address,
+    pub value: U256,
+}
+
+/// Decodes `log` as `E` if its first topic is the event signature
+pub fn decode_log<E: EthEvent + EthLogDecode>(log: &Log) -> Option<E> {
+    if log.topics.first() != Some(&E::signature()) {
+        return None;
+    }
+    let raw_log = RawLog {
+        topics: log.topics.clone(),
+        data: log.data.to_vec(),
+    };
+    <E as EthLogDecode>::decode_log(&raw_log).ok()
+}
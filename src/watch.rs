@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use anyhow::bail;
+use ethers::prelude::*;
+use futures::{Stream, StreamExt};
+use paris::Logger;
+
+use crate::{
+    backfill::backfill,
+    config::{Confirmation, ConfirmationConfig},
+    pipeline::{
+        fetch_block_data, log_block_data, log_provisional_block_data, revert_block_data, BlockData,
+        FeedContext,
+    },
+    provider::{get_finalized_block_number, get_ws_provider},
+    reorg::{block_number, find_fork, RecentBlocks},
+    sinks::Sinks,
+};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// A subscription without a new head for this long, five mainnet block
+/// times, is taken as stalled and reconnected
+const HEAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What the live watcher runs with for good
+pub struct WatchContext<'a> {
+    pub feed: FeedContext<'a>,
+    pub confirmations: &'a ConfirmationConfig,
+    /// blocks fetched at once when backfilling a gap
+    pub concurrency: usize,
+}
+
+/// Subscribes to new heads at `ws_url` and follows them for good. When the
+/// subscription can't be set up, ends, or following it fails, for instance on
+/// a node or database error, it is retried with exponential backoff and every
+/// block mined since `logged_through` is backfilled before following the new
+/// one. The wait only starts over once a round logged something.
+pub async fn watch_heads(
+    ws_url: &str,
+    ctx: &WatchContext<'_>,
+    sinks: &mut Sinks,
+    recent: &mut RecentBlocks,
+    logged_through: &mut Option<U64>,
+    logger: &mut Logger<'_>,
+) -> anyhow::Result<()> {
+    let text = ctx.feed.feed_config.output.logs_progress();
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+        let logged_before = *logged_through;
+        let round: anyhow::Result<()> = async {
+            let provider = get_ws_provider(ws_url, 2000).await?;
+            let mut stream = provider.subscribe_blocks().await?;
+            fill_gap(ctx, sinks, recent, logged_through, logger).await?;
+            follow_heads(ctx, &mut stream, sinks, recent, logged_through, logger).await
+        }
+        .await;
+        if *logged_through != logged_before {
+            delay = MIN_RECONNECT_DELAY;
+        }
+
+        let reason = match round {
+            Ok(()) => "New heads subscription ended".to_string(),
+            Err(err) => format!("Following new heads failed, {}", err),
+        };
+        let message = format!("{}, reconnecting in {}s", reason, delay.as_secs());
+        if text {
+            logger.done().warn(message);
+        } else {
+            // stdout only carries records then
+            eprintln!("{}", message);
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// The newest block `confirmation` lets through with `head` on top
+pub async fn confirmed_block(
+    client: &Provider<Http>,
+    confirmation: Confirmation,
+    head: U64,
+) -> anyhow::Result<U64> {
+    match confirmation {
+        Confirmation::Depth(depth) => Ok(head.saturating_sub(U64::from(depth))),
+        Confirmation::Finalized => get_finalized_block_number(client).await,
+    }
+}
+
+// Backfills the blocks mined since `logged_through` with `concurrency`,
+// after taking back logged blocks the chain replaced while nobody watched
+async fn fill_gap(
+    ctx: &WatchContext<'_>,
+    sinks: &mut Sinks,
+    recent: &mut RecentBlocks,
+    logged_through: &mut Option<U64>,
+    logger: &mut Logger<'_>,
+) -> anyhow::Result<()> {
+    let client = ctx.feed.client;
+    if let Some(logged) = *logged_through {
+        if let Some(canonical) = client.get_block(logged).await? {
+            if let Some(fork) = find_fork(client, recent, *logged_through, &canonical).await? {
+                let orphans = recent.split_off_after(fork.ancestor);
+                revert_orphans(orphans, ctx, sinks, logged_through, logger).await?;
+            }
+        }
+    }
+    let logged = match *logged_through {
+        Some(logged) => logged,
+        None => return Ok(()),
+    };
+
+    let head = client.get_block_number().await?;
+    let confirmed = confirmed_block(client, ctx.confirmations.confirmation, head).await?;
+    if confirmed <= logged {
+        return Ok(());
+    }
+
+    // blocks still waiting are backfilled along with the rest
+    recent.split_off_after(logged);
+    backfill(
+        &ctx.feed,
+        logged + 1_u64,
+        confirmed + 1_u64,
+        ctx.concurrency,
+        sinks,
+        recent,
+        logger,
+    )
+    .await?;
+    *logged_through = Some(confirmed);
+
+    Ok(())
+}
+
+/// Follows the new heads `stream` yields until it ends, or fails once it
+/// stalls for `HEAD_TIMEOUT`. Each head is joined to `recent` first: logged
+/// blocks it reorganized out are taken back in every output, newest first,
+/// and canonical blocks the stream skipped are queued before it. Queued blocks
+/// are then logged once they are confirmed deep enough. `logged_through` is
+/// the last block the sinks have.
+async fn follow_heads<S>(
+    ctx: &WatchContext<'_>,
+    stream: &mut S,
+    sinks: &mut Sinks,
+    recent: &mut RecentBlocks,
+    logged_through: &mut Option<U64>,
+    logger: &mut Logger<'_>,
+) -> anyhow::Result<()>
+where
+    S: Stream<Item = Block<TxHash>> + Unpin,
+{
+    let FeedContext {
+        client,
+        router,
+        token_map,
+        filters,
+        feed_config,
+    } = ctx.feed;
+    let text = feed_config.output.logs_progress();
+    if text {
+        logger.loading("Waiting for next transaction...");
+    }
+
+    loop {
+        let hash = match tokio::time::timeout(HEAD_TIMEOUT, stream.next()).await {
+            Ok(Some(header)) => match header.hash {
+                Some(hash) => hash,
+                None => continue,
+            },
+            Ok(None) => return Ok(()),
+            Err(_) => bail!("No new head for {}s", HEAD_TIMEOUT.as_secs()),
+        };
+        let block_data = fetch_block_data(client, router, hash, filters, feed_config).await?;
+        let head = block_number(&block_data);
+        let fork = match find_fork(client, recent, *logged_through, &block_data.block).await? {
+            Some(fork) => fork,
+            None => continue,
+        };
+
+        if fork.too_deep && text {
+            logger.done().warn(
+                "Reorg deeper than the kept blocks, older orphaned blocks can't be taken back",
+            );
+        }
+        let orphans = recent.split_off_after(fork.ancestor);
+        revert_orphans(orphans, ctx, sinks, logged_through, logger).await?;
+
+        for hash in fork.missing {
+            let missed = fetch_block_data(client, router, hash, filters, feed_config).await?;
+            queue_block(missed, ctx, recent, logger);
+        }
+        queue_block(block_data, ctx, recent, logger);
+
+        let confirmed = confirmed_block(client, ctx.confirmations.confirmation, head).await?;
+        let logged = *logged_through;
+        for block_data in recent.iter().filter(|block_data| {
+            let number = block_number(block_data);
+            number <= confirmed && logged.map_or(true, |logged| number > logged)
+        }) {
+            if text {
+                logger
+                    .done()
+                    .info(format!("New block {}", &block_data.block.hash.unwrap()));
+            }
+            log_block_data(block_data, token_map, feed_config, sinks).await?;
+            *logged_through = Some(block_number(block_data));
+        }
+        if let Some(logged) = *logged_through {
+            recent.prune(logged);
+        }
+
+        if text {
+            logger.loading("Waiting for next transaction...");
+        }
+    }
+}
+
+// takes back the orphans that were logged, newest first, and drops the rest
+async fn revert_orphans(
+    orphans: Vec<BlockData>,
+    ctx: &WatchContext<'_>,
+    sinks: &mut Sinks,
+    logged_through: &mut Option<U64>,
+    logger: &mut Logger<'_>,
+) -> anyhow::Result<()> {
+    let FeedContext {
+        token_map,
+        feed_config,
+        ..
+    } = ctx.feed;
+    let text = feed_config.output.logs_progress();
+    for orphan in orphans {
+        let number = block_number(&orphan);
+        let hash = orphan.block.hash.unwrap();
+        if logged_through.map_or(false, |logged| number <= logged) {
+            if text {
+                logger
+                    .done()
+                    .warn(format!("Block {} reorganized out", hash));
+            }
+            revert_block_data(&orphan, token_map, feed_config, sinks).await?;
+            *logged_through = Some(number - 1_u64);
+        } else if ctx.confirmations.provisional {
+            logger
+                .done()
+                .warn(format!("Provisional block {} reorganized out", hash));
+        }
+    }
+
+    Ok(())
+}
+
+// keeps the block until it's confirmed, printing it meanwhile when asked to
+fn queue_block(
+    block_data: BlockData,
+    ctx: &WatchContext<'_>,
+    recent: &mut RecentBlocks,
+    logger: &mut Logger<'_>,
+) {
+    if ctx.confirmations.provisional {
+        logger.done().log(format!(
+            "Provisional block {}",
+            &block_data.block.hash.unwrap()
+        ));
+        log_provisional_block_data(&block_data, ctx.feed.token_map, ctx.feed.feed_config);
+    }
+    recent.push(block_data);
+}